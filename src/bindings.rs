@@ -0,0 +1,520 @@
+use ncurses::{attr_t, A_BOLD, A_NORMAL, KEY_BTAB, KEY_DC, KEY_DOWN, KEY_END, KEY_F0, KEY_HOME, KEY_IC, KEY_LEFT, KEY_NPAGE,
+              KEY_PPAGE, KEY_RIGHT, KEY_SEND, KEY_SF, KEY_SHOME, KEY_SLEFT, KEY_SR, KEY_SRIGHT,
+              KEY_UP};
+use std::collections::BTreeMap;
+use toml::Spanned;
+use toml::Value;
+
+const ENTER: i32 = 10;
+const ESC: i32 = 27;
+const BACKSPACE: i32 = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    ScrollToTop,
+    ScrollToBottom,
+    Play,
+    Restart,
+    Next,
+    Prev,
+    SeekBackward,
+    SeekForward,
+    FastSeekBackward,
+    FastSeekForward,
+    //Percent of the duration, 0 to 90
+    SeekPercent(u8),
+    SeekTo,
+    NextChapter,
+    PrevChapter,
+    Chapters,
+    CycleAudio,
+    CycleSubtitles,
+    CycleVideo,
+    Tracks,
+    VolumeUp,
+    VolumeDown,
+    SpeedUp,
+    SpeedDown,
+    ToggleMute,
+    TogglePause,
+    Stop,
+    JumpToCurrent,
+    Search,
+    SearchNext,
+    CommandLine,
+    Remove,
+    MoveUp,
+    MoveDown,
+    Grab,
+    ToggleMark,
+    Visual,
+    ClearMarks,
+    MoveToTop,
+    MoveToBottom,
+    Crop,
+    PlayNext,
+    Shuffle,
+    UpdatePlaylist,
+    CycleLoopFile,
+    CycleLoopPlaylist,
+    ToggleConsume,
+    MessageHistory,
+    Help,
+    ToggleBrowser,
+    TogglePlaylists,
+    Add,
+    ToggleHidden,
+    CycleSort,
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Navigation,
+    Playback,
+    Playlist,
+    Browser,
+    General,
+}
+
+const CATEGORIES: &[(Category, &str)] = &[
+    (Category::Navigation, "Navigation"),
+    (Category::Playback, "Playback"),
+    (Category::Playlist, "Playlist"),
+    (Category::Browser, "File browser"),
+    (Category::General, "General"),
+];
+
+/// Every bindable action with its name in the `[keys]` section, a description for the help
+/// screen and its default keys.
+const ACTIONS: &[(Action, &str, &str, &[&str])] = &[
+    (Action::ScrollUp, "scroll_up", "Scroll up", &["<Up>"]),
+    (Action::ScrollDown, "scroll_down", "Scroll down", &["<Down>"]),
+    (Action::PageUp, "page_up", "Page up", &["<PageUp>"]),
+    (Action::PageDown, "page_down", "Page down", &["<PageDown>"]),
+    (Action::ScrollToTop, "scroll_to_top", "Go to first entry", &["gg", "<Home>"]),
+    (Action::ScrollToBottom, "scroll_to_bottom", "Go to last entry", &["G", "<End>"]),
+    (Action::Play, "play", "Play / open directory", &["<Enter>"]),
+    (Action::Restart, "restart", "Restart playback", &["<Backspace>"]),
+    (Action::Next, "next", "Play next song", &[">"]),
+    (Action::Prev, "prev", "Play previous song", &["<"]),
+    (Action::SeekBackward, "seek_backward", "Seek backward", &["<Left>"]),
+    (Action::SeekForward, "seek_forward", "Seek forward", &["<Right>"]),
+    (Action::FastSeekBackward, "fast_seek_backward", "Seek backward fast", &["<S-Left>"]),
+    (Action::FastSeekForward, "fast_seek_forward", "Seek forward fast", &["<S-Right>"]),
+    (Action::SeekPercent(0), "seek_0", "Seek to the beginning", &["0"]),
+    (Action::SeekPercent(10), "seek_10", "Seek to 10%", &["1"]),
+    (Action::SeekPercent(20), "seek_20", "Seek to 20%", &["2"]),
+    (Action::SeekPercent(30), "seek_30", "Seek to 30%", &["3"]),
+    (Action::SeekPercent(40), "seek_40", "Seek to 40%", &["4"]),
+    (Action::SeekPercent(50), "seek_50", "Seek to 50%", &["5"]),
+    (Action::SeekPercent(60), "seek_60", "Seek to 60%", &["6"]),
+    (Action::SeekPercent(70), "seek_70", "Seek to 70%", &["7"]),
+    (Action::SeekPercent(80), "seek_80", "Seek to 80%", &["8"]),
+    (Action::SeekPercent(90), "seek_90", "Seek to 90%", &["9"]),
+    (Action::SeekTo, "seek_to", "Seek to a time", &["t"]),
+    (Action::NextChapter, "next_chapter", "Next chapter", &[")"]),
+    (Action::PrevChapter, "prev_chapter", "Previous chapter", &["("]),
+    (Action::Chapters, "chapters", "Choose a chapter", &["H"]),
+    (Action::CycleAudio, "cycle_audio", "Next audio track", &["#"]),
+    (Action::CycleSubtitles, "cycle_subtitles", "Next subtitle track", &["j"]),
+    (Action::CycleVideo, "cycle_video", "Next video track", &["_"]),
+    (Action::Tracks, "tracks", "Choose audio, subtitle and video tracks", &["i"]),
+    (Action::VolumeUp, "volume_up", "Volume up", &["+"]),
+    (Action::VolumeDown, "volume_down", "Volume down", &["-"]),
+    (Action::SpeedUp, "speed_up", "Speed up", &["]"]),
+    (Action::SpeedDown, "speed_down", "Speed down", &["["]),
+    (Action::ToggleMute, "toggle_mute", "Toggle mute", &["m"]),
+    (Action::TogglePause, "toggle_pause", "Toggle playback", &["p"]),
+    (Action::Stop, "stop", "Stop playback", &["s"]),
+    (Action::JumpToCurrent, "jump_to_current", "Jump to current song", &["o"]),
+    (Action::Search, "search", "Search", &["/"]),
+    (Action::SearchNext, "search_next", "Next search result", &["n"]),
+    (Action::CommandLine, "command_line", "Command line", &[":"]),
+    (Action::Remove, "remove", "Remove from playlist", &["r"]),
+    (Action::MoveUp, "move_up", "Move entry up", &["K"]),
+    (Action::MoveDown, "move_down", "Move entry down", &["J"]),
+    (Action::Grab, "grab", "Grab entry / drop it here", &["x"]),
+    (Action::ToggleMark, "toggle_mark", "Mark/unmark entry", &["<Space>"]),
+    (Action::Visual, "visual", "Start/end visual selection", &["v"]),
+    (Action::ClearMarks, "clear_marks", "Unmark all entries", &["V"]),
+    (Action::MoveToTop, "move_to_top", "Move marked entries to the top", &["T"]),
+    (Action::MoveToBottom, "move_to_bottom", "Move marked entries to the bottom", &["B"]),
+    (Action::Crop, "crop", "Remove all but the marked entries", &["C"]),
+    (Action::PlayNext, "play_next", "Play marked entries next", &["N"]),
+    (Action::Shuffle, "shuffle", "Shuffle playlist", &["z"]),
+    (Action::UpdatePlaylist, "update_playlist", "Force playlist update", &["u"]),
+    (Action::CycleLoopFile, "cycle_loop_file", "Toggle loop-file", &["l"]),
+    (Action::CycleLoopPlaylist, "cycle_loop_playlist", "Toggle loop-playlist", &["L"]),
+    (Action::ToggleConsume, "toggle_consume", "Toggle consume mode", &["c"]),
+    (Action::MessageHistory, "message_history", "Show message history", &["M"]),
+    (Action::Help, "help", "Show this help", &["?", "<F1>"]),
+    (Action::ToggleBrowser, "toggle_browser", "Switch to/from file browser", &["<Tab>"]),
+    (Action::TogglePlaylists, "toggle_playlists", "Switch to/from saved playlists", &["P"]),
+    (Action::Add, "add", "Append file or directory", &["a"]),
+    (Action::ToggleHidden, "toggle_hidden", "Show/hide hidden files", &["."]),
+    (Action::CycleSort, "cycle_sort", "Change sort order", &["S"]),
+    (Action::Quit, "quit", "Quit ncmpvc", &["q"]),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|&&(_, n, _, _)| n == name)
+            .map(|&(action, _, _, _)| action)
+    }
+
+    fn category(self) -> Category {
+        match self {
+            Action::ScrollUp |
+            Action::ScrollDown |
+            Action::PageUp |
+            Action::PageDown |
+            Action::ScrollToTop |
+            Action::ScrollToBottom |
+            Action::JumpToCurrent |
+            Action::Search |
+            Action::SearchNext => Category::Navigation,
+            Action::Play |
+            Action::Restart |
+            Action::Next |
+            Action::Prev |
+            Action::SeekBackward |
+            Action::SeekForward |
+            Action::FastSeekBackward |
+            Action::FastSeekForward |
+            Action::SeekPercent(_) |
+            Action::SeekTo |
+            Action::NextChapter |
+            Action::PrevChapter |
+            Action::Chapters |
+            Action::CycleAudio |
+            Action::CycleSubtitles |
+            Action::CycleVideo |
+            Action::Tracks |
+            Action::VolumeUp |
+            Action::VolumeDown |
+            Action::SpeedUp |
+            Action::SpeedDown |
+            Action::ToggleMute |
+            Action::TogglePause |
+            Action::Stop => Category::Playback,
+            Action::Remove |
+            Action::MoveUp |
+            Action::MoveDown |
+            Action::Grab |
+            Action::ToggleMark |
+            Action::Visual |
+            Action::ClearMarks |
+            Action::MoveToTop |
+            Action::MoveToBottom |
+            Action::Crop |
+            Action::PlayNext |
+            Action::Shuffle |
+            Action::UpdatePlaylist |
+            Action::CycleLoopFile |
+            Action::CycleLoopPlaylist |
+            Action::ToggleConsume => Category::Playlist,
+            Action::ToggleBrowser |
+            Action::TogglePlaylists |
+            Action::Add |
+            Action::ToggleHidden |
+            Action::CycleSort => Category::Browser,
+            Action::CommandLine | Action::MessageHistory | Action::Help | Action::Quit => {
+                Category::General
+            }
+        }
+    }
+}
+
+pub struct KeyError {
+    pub pos: usize,
+    pub msg: String,
+}
+
+pub struct KeyMap {
+    //The key codes, the bound action and the keys as written in the configuration
+    bindings: Vec<(Vec<i32>, Action, String)>,
+}
+
+enum Lookup {
+    Action(Action),
+    Pending,
+    Unbound,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::new(BTreeMap::new()).unwrap_or_else(|_| unreachable!("invalid default key"))
+    }
+}
+
+impl KeyMap {
+    /// Builds the key map from the `[keys]` section of the configuration file.
+    /// Actions listed there lose their default keys, defaults which collide with a user
+    /// binding are dropped. Colliding user bindings are reported as an error.
+    pub fn new(user: BTreeMap<String, Spanned<Value>>) -> Result<KeyMap, KeyError> {
+        let mut configured = vec![];
+        let mut user_bindings: Vec<(Vec<i32>, Action, usize, String)> = vec![];
+
+        let mut entries: Vec<(String, Spanned<Value>)> = user.into_iter().collect();
+        entries.sort_by_key(|(_, value)| value.start());
+
+        for (name, value) in entries {
+            let pos = value.start();
+            let action = match Action::from_name(&name) {
+                Some(action) => action,
+                None => {
+                    return Err(KeyError {
+                        pos,
+                        msg: format!("unknown action `{}`", name),
+                    })
+                }
+            };
+            configured.push(action);
+
+            let keys = match value.into_inner() {
+                Value::String(key) => vec![key],
+                Value::Array(ref keys) if keys.iter().all(Value::is_str) => keys
+                    .iter()
+                    .filter_map(|key| key.as_str().map(String::from))
+                    .collect(),
+                _ => {
+                    return Err(KeyError {
+                        pos,
+                        msg: format!("`keys.{}` must be a key or a list of keys", name),
+                    })
+                }
+            };
+
+            for key in keys {
+                let sequence = parse_keys(&key).map_err(|msg| KeyError { pos, msg })?;
+                for &(ref other, other_action, _, ref other_key) in &user_bindings {
+                    let duplicate = other_action == action && *other == sequence;
+                    if collide(&sequence, other) && !duplicate {
+                        return Err(KeyError {
+                            pos,
+                            msg: format!(
+                                "`{}` of `{}` conflicts with `{}` of `{}`",
+                                key,
+                                name,
+                                other_key,
+                                action_name(other_action)
+                            ),
+                        });
+                    }
+                }
+                user_bindings.push((sequence, action, pos, key));
+            }
+        }
+
+        let mut bindings: Vec<(Vec<i32>, Action, String)> = user_bindings
+            .into_iter()
+            .map(|(sequence, action, _, key)| (sequence, action, key))
+            .collect();
+
+        for &(action, _, _, defaults) in ACTIONS {
+            if configured.contains(&action) {
+                continue;
+            }
+            for key in defaults {
+                let sequence = parse_keys(key).map_err(|msg| KeyError { pos: 0, msg })?;
+                if !bindings.iter().any(|(other, _, _)| collide(&sequence, other)) {
+                    bindings.push((sequence, action, String::from(*key)));
+                }
+            }
+        }
+
+        Ok(KeyMap { bindings })
+    }
+
+    /// Appends `key` to the pending key sequence and returns the bound action as soon as the
+    /// sequence is complete. Unknown sequences are discarded.
+    pub fn feed(&self, pending: &mut Vec<i32>, key: i32) -> Option<Action> {
+        pending.push(key);
+        match self.lookup(pending) {
+            Lookup::Action(action) => {
+                pending.clear();
+                Some(action)
+            }
+            Lookup::Pending => None,
+            Lookup::Unbound => {
+                //The key may start a new sequence on its own
+                let retry = pending.len() > 1;
+                pending.clear();
+                if retry {
+                    self.feed(pending, key)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// The lines of the help screen: every action grouped by category with its keys.
+    /// Keys which differ from the defaults are marked and the defaults are shown next to them.
+    pub fn help(&self) -> Vec<(String, attr_t)> {
+        let mut lines = vec![];
+        for &(category, title) in CATEGORIES {
+            if !lines.is_empty() {
+                lines.push((String::new(), A_NORMAL()));
+            }
+            lines.push((String::from(title), A_BOLD()));
+            for &(action, _, description, defaults) in ACTIONS {
+                if action.category() != category {
+                    continue;
+                }
+                let keys: Vec<&str> = self
+                    .bindings
+                    .iter()
+                    .filter(|&&(_, a, _)| a == action)
+                    .map(|(_, _, key)| key.as_str())
+                    .collect();
+                let mut line = format!(
+                    "  {:<30}{}",
+                    description,
+                    if keys.is_empty() {
+                        String::from("unbound")
+                    } else {
+                        keys.join(", ")
+                    }
+                );
+                let changed = keys != defaults;
+                if changed {
+                    line.push_str(&format!("  (default: {})", defaults.join(", ")));
+                }
+                lines.push((line, if changed { A_BOLD() } else { A_NORMAL() }));
+            }
+        }
+        lines
+    }
+
+    /// Whether a binding like `<M-x>` starts with `ESC`.
+    pub fn has_meta_keys(&self) -> bool {
+        self.bindings.iter().any(|(keys, _, _)| keys.len() > 1 && keys[0] == ESC)
+    }
+
+    fn lookup(&self, keys: &[i32]) -> Lookup {
+        let mut pending = false;
+        for &(ref sequence, action, _) in &self.bindings {
+            if sequence.as_slice() == keys {
+                return Lookup::Action(action);
+            } else if sequence.starts_with(keys) {
+                pending = true;
+            }
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS
+        .iter()
+        .find(|&&(a, _, _, _)| a == action)
+        .map(|&(_, name, _, _)| name)
+        .unwrap_or("")
+}
+
+/// Two sequences collide if one of them can never be completed because of the other.
+fn collide(a: &[i32], b: &[i32]) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Parses a key sequence like `gg`, `<C-d>`, `<M-x>` or `<S-Left>` into curses key codes.
+fn parse_keys(keys: &str) -> Result<Vec<i32>, String> {
+    let mut output = vec![];
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                if end > 1 {
+                    output.extend(parse_special(&rest[1..end])?);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        output.push(c as i32);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if output.is_empty() {
+        Err(String::from("empty key binding"))
+    } else {
+        Ok(output)
+    }
+}
+
+fn parse_special(name: &str) -> Result<Vec<i32>, String> {
+    let lower = name.to_lowercase();
+    let unknown = || format!("unknown key `<{}>`", name);
+
+    //Terminals send Alt+<key> as escape followed by the key
+    if lower.starts_with("m-") || lower.starts_with("a-") {
+        let key = parse_keys(&format!("<{}>", &name[2..]))?;
+        return if key.len() == 1 {
+            Ok(vec![ESC, key[0]])
+        } else {
+            Err(unknown())
+        };
+    }
+
+    if lower.starts_with("c-") {
+        let mut chars = name[2..].chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() || "@[\\]^_".contains(c) => {
+                Ok(vec![c.to_ascii_uppercase() as i32 & 0x1f])
+            }
+            _ => Err(unknown()),
+        };
+    }
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(vec![c as i32]);
+    }
+
+    if let Some(Ok(n)) = lower.strip_prefix('f').map(str::parse::<i32>) {
+        if (1..=63).contains(&n) {
+            return Ok(vec![KEY_F0 + n]);
+        }
+    }
+
+    let key = match lower.as_str() {
+        "enter" | "return" | "cr" => ENTER,
+        "esc" | "escape" => ESC,
+        "backspace" | "bs" => BACKSPACE,
+        "space" => ' ' as i32,
+        "tab" => '\t' as i32,
+        "lt" => '<' as i32,
+        "gt" => '>' as i32,
+        "up" => KEY_UP,
+        "down" => KEY_DOWN,
+        "left" => KEY_LEFT,
+        "right" => KEY_RIGHT,
+        "pageup" | "pgup" => KEY_PPAGE,
+        "pagedown" | "pgdn" => KEY_NPAGE,
+        "home" => KEY_HOME,
+        "end" => KEY_END,
+        "insert" | "ins" => KEY_IC,
+        "delete" | "del" => KEY_DC,
+        "s-up" => KEY_SR,
+        "s-down" => KEY_SF,
+        "s-left" => KEY_SLEFT,
+        "s-right" => KEY_SRIGHT,
+        "s-home" => KEY_SHOME,
+        "s-end" => KEY_SEND,
+        "s-tab" => KEY_BTAB,
+        _ => return Err(unknown()),
+    };
+    Ok(vec![key])
+}
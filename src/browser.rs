@@ -0,0 +1,308 @@
+use ncurses::*;
+use playlists;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use text::fit;
+use theme::{self, Element};
+
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "alac", "ape", "au", "avi", "cue", "flac", "m3u", "m3u8", "m4a", "m4b",
+    "m4v", "mka", "mkv", "mov", "mp2", "mp3", "mp4", "mpc", "mpeg", "mpg", "oga", "ogg", "ogv",
+    "opus", "pls", "spx", "tta", "wav", "webm", "wma", "wmv", "wv",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Name,
+    Extension,
+    Modified,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match name {
+            "name" => Some(SortOrder::Name),
+            "extension" => Some(SortOrder::Extension),
+            "modified" => Some(SortOrder::Modified),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortOrder::Name => "name",
+            SortOrder::Extension => "extension",
+            SortOrder::Modified => "modified",
+        }
+    }
+
+    fn next(self) -> SortOrder {
+        match self {
+            SortOrder::Name => SortOrder::Extension,
+            SortOrder::Extension => SortOrder::Modified,
+            SortOrder::Modified => SortOrder::Name,
+        }
+    }
+}
+
+/// What a browser lists.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    //Directories and media files
+    Files,
+    //The playlist files in one directory
+    Playlists,
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub name: String,
+    pub is_dir: bool,
+    modified: SystemTime,
+}
+
+pub struct Browser {
+    pub kind: Kind,
+    pub dir: PathBuf,
+    pub entries: Vec<Entry>,
+    pub top_line: usize,
+    pub selected_line: usize,
+    pub show_hidden: bool,
+    pub sort: SortOrder,
+}
+
+impl Browser {
+    pub fn new(kind: Kind, root: &Path, show_hidden: bool, sort: SortOrder) -> Browser {
+        let mut browser = Browser {
+            kind,
+            dir: root.to_path_buf(),
+            entries: vec![],
+            top_line: 0,
+            selected_line: 0,
+            show_hidden,
+            sort,
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Re-reads the current directory. When browsing files the first entry always leads to the
+    /// parent directory.
+    pub fn refresh(&mut self) {
+        self.entries.clear();
+        let kind = self.kind;
+        if let (Kind::Files, Some(parent)) = (kind, self.dir.parent()) {
+            self.entries.push(Entry {
+                path: parent.to_path_buf(),
+                name: String::from(".."),
+                is_dir: true,
+                modified: SystemTime::UNIX_EPOCH,
+            });
+        }
+
+        let mut entries: Vec<Entry> = read_dir(&self.dir, self.show_hidden)
+            .into_iter()
+            .filter(|entry| match kind {
+                Kind::Files => entry.is_dir || is_media_file(&entry.path),
+                Kind::Playlists => !entry.is_dir && playlists::is_playlist(&entry.path),
+            })
+            .collect();
+        let sort = self.sort;
+        entries.sort_by(|a, b| compare(a, b, sort));
+        self.entries.extend(entries);
+
+        if self.selected_line >= self.entries.len() {
+            self.selected_line = self.entries.len().saturating_sub(1);
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.selected_line)
+    }
+
+    /// Changes into `dir` and selects the entry we came from when moving up.
+    pub fn open(&mut self, dir: PathBuf, height: usize) {
+        let previous = self.dir.clone();
+        self.dir = dir;
+        self.selected_line = 0;
+        self.top_line = 0;
+        self.refresh();
+        if let Some(line) = self.entries.iter().position(|entry| entry.path == previous) {
+            self.select(line, height);
+        }
+    }
+
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+        self.refresh();
+    }
+
+    pub fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.refresh();
+    }
+
+    /// Moves the selection by `offset` lines and scrolls so it stays visible in `height` lines.
+    pub fn move_selection(&mut self, offset: isize, height: usize) {
+        let line = self.selected_line as isize + offset;
+        let line = if line < 0 { 0 } else { line as usize };
+        self.select(line, height);
+    }
+
+    pub fn select(&mut self, line: usize, height: usize) {
+        let list_height = list_height(height);
+        self.selected_line = std::cmp::min(line, self.entries.len().saturating_sub(1));
+        if self.selected_line < self.top_line {
+            self.top_line = self.selected_line;
+        } else if self.selected_line >= self.top_line + list_height {
+            self.top_line = self.selected_line + 1 - list_height;
+        }
+    }
+}
+
+/// Returns all media files below `path` (or `path` itself if it is a file) in browsing order.
+pub fn collect_media(path: &Path, show_hidden: bool, sort: SortOrder) -> Vec<PathBuf> {
+    let mut output = vec![];
+    collect(path, show_hidden, sort, &mut HashSet::new(), &mut output);
+    output
+}
+
+//`visited` holds the directories already read, resolved, so a link to a parent can't loop
+fn collect(
+    path: &Path,
+    show_hidden: bool,
+    sort: SortOrder,
+    visited: &mut HashSet<PathBuf>,
+    output: &mut Vec<PathBuf>,
+) {
+    if !path.is_dir() {
+        if is_media_file(path) {
+            output.push(path.to_path_buf());
+        }
+        return;
+    }
+    if !fs::canonicalize(path).map(|dir| visited.insert(dir)).unwrap_or(false) {
+        return;
+    }
+
+    let mut entries = read_dir(path, show_hidden);
+    entries.sort_by(|a, b| compare(a, b, sort));
+    for entry in entries {
+        if entry.is_dir {
+            collect(&entry.path, show_hidden, sort, visited, output);
+        } else if is_media_file(&entry.path) {
+            output.push(entry.path);
+        }
+    }
+}
+
+pub fn print_browser(win: WINDOW, browser: &Browser) {
+    let max_x = getmaxx(win) as usize;
+    let height = getmaxy(win) as usize;
+    werase(win);
+
+    wmove(win, 0, 0);
+    wattron(win, A_BOLD());
+    let header = format!(
+        "{}: {}  [sort: {}{}]",
+        match browser.kind {
+            Kind::Files => "Browse",
+            Kind::Playlists => "Playlists",
+        },
+        browser.dir.display(),
+        browser.sort.name(),
+        if browser.show_hidden { ", hidden" } else { "" }
+    );
+    waddstr(win, &fit(&header, max_x));
+    wattroff(win, A_BOLD());
+
+    let to = std::cmp::min(browser.top_line + list_height(height), browser.entries.len());
+    for (y, i) in (browser.top_line..to).enumerate() {
+        let entry = &browser.entries[i];
+        wmove(win, y as i32 + 1, 0);
+        let mut attributes = if entry.is_dir { A_BOLD() } else { A_NORMAL() };
+        if i == browser.selected_line {
+            attributes = theme::merge(attributes, theme::attributes(Element::Selected));
+        }
+        wattrset(win, attributes);
+        let name = if entry.is_dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        waddstr(win, &fit(&name, max_x));
+        wattrset(win, A_NORMAL());
+    }
+
+    if browser.entries.iter().all(|entry| entry.name == "..") {
+        wmove(win, browser.entries.len() as i32 + 1, 0);
+        waddstr(
+            win,
+            match browser.kind {
+                Kind::Files => "Directory is empty",
+                Kind::Playlists => "No saved playlists, use :save <name> to add one",
+            },
+        );
+    }
+    wrefresh(win);
+}
+
+/// The first line of the window shows the current directory.
+fn list_height(height: usize) -> usize {
+    std::cmp::max(height, 2) - 1
+}
+
+fn read_dir(dir: &Path, show_hidden: bool) -> Vec<Entry> {
+    let mut output = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !show_hidden && name.starts_with('.') {
+                continue;
+            }
+            //Follow symlinks so linked directories can be browsed too
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            output.push(Entry {
+                path: entry.path(),
+                name,
+                is_dir: metadata.is_dir(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    output
+}
+
+fn is_media_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            MEDIA_EXTENSIONS.contains(&ext.as_str())
+        }
+        None => false,
+    }
+}
+
+/// Directories come first, then entries are ordered by the given sort order.
+fn compare(a: &Entry, b: &Entry, sort: SortOrder) -> Ordering {
+    let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+    b.is_dir.cmp(&a.is_dir).then_with(|| match sort {
+        SortOrder::Name => by_name(),
+        SortOrder::Extension => extension(&a.path)
+            .cmp(&extension(&b.path))
+            .then_with(by_name),
+        SortOrder::Modified => b.modified.cmp(&a.modified).then_with(by_name),
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
@@ -0,0 +1,113 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+
+pub const DEFAULT_SOCKET: &str = "/tmp/mpvsocket";
+
+/// Environment variables consulted (in this order) when no socket is given on the command line.
+const SOCKET_ENV_VARS: [&str; 2] = ["NCMPVC_SOCKET", "MPV_SOCKET"];
+
+pub struct Args {
+    pub socket: Option<String>,
+    pub config: Option<String>,
+}
+
+impl Args {
+    /// Resolves the socket path from the command line, the environment, the configuration file
+    /// and the default (in this order) and exits with an error message if it is not usable.
+    pub fn socket(&self, configured: Option<&String>) -> String {
+        let socket = self
+            .socket
+            .clone()
+            .or_else(socket_from_env)
+            .or_else(|| configured.cloned())
+            .unwrap_or_else(|| DEFAULT_SOCKET.to_string());
+        check_socket(&socket);
+
+        socket
+    }
+}
+
+/// Parses the command line arguments. `--help` and `--version` print their output and exit,
+/// invalid arguments exit with an error message.
+pub fn parse() -> Args {
+    let mut socket = None;
+    let mut config = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print_help();
+                std::process::exit(0);
+            }
+            "-V" | "--version" => {
+                println!("ncmpvc {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            "-s" | "--socket" => match args.next() {
+                Some(path) => socket = Some(path),
+                None => error!("Error: '{}' requires a path argument", arg),
+            },
+            "-c" | "--config" => match args.next() {
+                Some(path) => config = Some(path),
+                None => error!("Error: '{}' requires a path argument", arg),
+            },
+            _ if arg.starts_with("--socket=") => {
+                socket = Some(arg["--socket=".len()..].to_string());
+            }
+            _ if arg.starts_with("--config=") => {
+                config = Some(arg["--config=".len()..].to_string());
+            }
+            _ => error!(
+                "Error: Unknown argument '{}'\nTry 'ncmpvc --help' for more information.",
+                arg
+            ),
+        }
+    }
+
+    Args { socket, config }
+}
+
+fn socket_from_env() -> Option<String> {
+    SOCKET_ENV_VARS
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|path| !path.is_empty())
+}
+
+fn check_socket(path: &str) {
+    match fs::metadata(path) {
+        Ok(meta) => {
+            if !meta.file_type().is_socket() {
+                error!("Error: '{}' is not a socket", path);
+            }
+        }
+        Err(why) => error!("Error: Could not access mpv socket '{}': {}", path, why),
+    }
+}
+
+fn print_help() {
+    println!(
+        "ncmpvc {}
+A ncurses client for mpv
+
+USAGE:
+    ncmpvc [OPTIONS]
+
+OPTIONS:
+    -s, --socket <PATH>    Path to the mpv IPC socket (default: {})
+    -c, --config <PATH>    Path to the configuration file
+                           (default: $XDG_CONFIG_HOME/ncmpvc/config.toml)
+    -h, --help             Print this help and exit
+    -V, --version          Print version information and exit
+
+ENVIRONMENT:
+    NCMPVC_SOCKET          Socket path used when --socket is not given
+    MPV_SOCKET             Fallback if NCMPVC_SOCKET is not set
+
+Start mpv with: mpv --input-ipc-server=<PATH> --idle",
+        env!("CARGO_PKG_VERSION"),
+        DEFAULT_SOCKET
+    );
+}
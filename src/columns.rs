@@ -0,0 +1,148 @@
+use get_pretty_time;
+use mpvipc::ipc::PlaylistEntry;
+use std::path::Path;
+use tags::Info;
+use text;
+
+/// What a column of the playlist shows or a search looks at.
+#[derive(PartialEq)]
+pub enum Field {
+    //The position in the playlist
+    Index,
+    //The title tag, falling back to what mpv shows
+    Title,
+    Filename,
+    Path,
+    Duration,
+    Tag(String),
+}
+
+impl Field {
+    /// Anything which isn't one of the others is a tag.
+    pub fn from_name(name: &str) -> Field {
+        match name {
+            "index" => Field::Index,
+            "title" => Field::Title,
+            "filename" => Field::Filename,
+            "path" => Field::Path,
+            "duration" => Field::Duration,
+            tag => Field::Tag(tag.to_lowercase()),
+        }
+    }
+
+    /// Whether the files have to be read for this field, mpv only knows the current one.
+    pub fn needs_tags(&self) -> bool {
+        matches!(*self, Field::Duration | Field::Tag(_))
+    }
+
+    pub fn text(&self, entry: &PlaylistEntry, info: Option<&Info>) -> String {
+        let tag = |name| info.and_then(|info| info.tag(name)).map(str::to_string);
+        match *self {
+            Field::Index => entry.id.to_string(),
+            Field::Title => tag("title").unwrap_or_else(|| {
+                if entry.title.is_empty() {
+                    entry.filename.clone()
+                } else {
+                    entry.title.clone()
+                }
+            }),
+            Field::Filename => Path::new(&entry.filename)
+                .file_name()
+                .map_or(entry.filename.clone(), |name| name.to_string_lossy().into_owned()),
+            Field::Path => entry.filename.clone(),
+            Field::Duration => info
+                .and_then(|info| info.duration)
+                .map_or(String::new(), get_pretty_time),
+            Field::Tag(ref name) => tag(name).unwrap_or_default(),
+        }
+    }
+}
+
+pub enum Width {
+    Fixed(usize),
+    //Of the space the fixed columns leave
+    Percent(usize),
+    //Shares what is left with the other columns without a width
+    Auto,
+}
+
+pub struct Column {
+    pub field: Field,
+    pub width: Width,
+    pub header: String,
+}
+
+impl Column {
+    pub fn new(name: &str, width: Width, header: Option<String>) -> Column {
+        let field = Field::from_name(name);
+        let header = header.unwrap_or_else(|| match field {
+            Field::Index => String::from("#"),
+            Field::Duration => String::from("Time"),
+            Field::Filename => String::from("File"),
+            _ => {
+                let mut chars = name.chars();
+                chars.next().map_or(String::new(), |first| {
+                    first.to_uppercase().chain(chars).collect()
+                })
+            }
+        });
+        Column { field, width, header }
+    }
+
+    /// The index and the title, like before the columns could be configured.
+    pub fn defaults() -> Vec<Column> {
+        vec![
+            Column::new("index", Width::Fixed(4), None),
+            Column::new("title", Width::Auto, None),
+        ]
+    }
+
+    pub fn is_right_aligned(&self) -> bool {
+        matches!(self.field, Field::Duration)
+    }
+
+    /// Fits `text` into the column, durations are aligned on the right.
+    pub fn cell(&self, text: &str, width: usize) -> String {
+        if self.is_right_aligned() {
+            text::fit_right(text, width)
+        } else {
+            text::fit(text, width)
+        }
+    }
+}
+
+/// The widths of the columns in a line of `width` cells. `separators` are taken by the space
+/// between the columns.
+pub fn widths(columns: &[Column], width: usize, separators: usize) -> Vec<usize> {
+    let mut left = width.saturating_sub(separators);
+    let mut widths: Vec<usize> = columns
+        .iter()
+        .map(|column| match column.width {
+            Width::Fixed(width) => width,
+            _ => 0,
+        })
+        .collect();
+    for width in &mut widths {
+        *width = std::cmp::min(*width, left);
+        left -= *width;
+    }
+
+    let flexible = left;
+    for (column, width) in columns.iter().zip(&mut widths) {
+        if let Width::Percent(percent) = column.width {
+            *width = std::cmp::min(flexible * percent / 100, left);
+            left -= *width;
+        }
+    }
+
+    let mut share = columns.iter().filter(|column| matches!(column.width, Width::Auto)).count();
+    for (column, width) in columns.iter().zip(&mut widths) {
+        if let Width::Auto = column.width {
+            //The last ones get what doesn't divide evenly
+            *width = left / share;
+            left -= *width;
+            share -= 1;
+        }
+    }
+    widths
+}
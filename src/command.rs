@@ -0,0 +1,449 @@
+use config;
+use error::{self, Error};
+use mpv_ext::{self, json_escape, MpvExt};
+use mpvipc::{Mpv, NumberChangeOptions, SeekOptions};
+use playlists::{self, Item};
+use serde_json::{self, Map, Value};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tags::Tags;
+
+const HISTORY_LENGTH: usize = 1000;
+
+/// Every command with a short description of its arguments.
+const COMMANDS: &[(&str, &str)] = &[
+    ("load", "<file> [replace|append|append-play]"),
+    ("loadlist", "<playlist> [replace|append]"),
+    ("quit", ""),
+    ("raw", "<json command>"),
+    ("save", "<playlist>"),
+    ("seek", "[+|-][[hh:]mm:]ss | [+|-]<percent>%"),
+    ("set", "<property> <value>"),
+    ("speed", "[+|-]<speed>"),
+    ("vol", "[+|-]<volume>"),
+    ("volume", "[+|-]<volume>"),
+];
+
+const LOAD_MODES: &[&str] = &["replace", "append", "append-play"];
+const LOADLIST_MODES: &[&str] = &["replace", "append"];
+
+/// A line entered on the `:` command line.
+pub enum Command {
+    Seek(f64, SeekOptions),
+    Volume(f64, NumberChangeOptions),
+    Speed(f64, NumberChangeOptions),
+    Load(PathBuf, String),
+    LoadList(PathBuf, String),
+    Save(PathBuf),
+    Set(String, String),
+    Raw(Value),
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> error::Result<Command> {
+        let words: Vec<String> = split_words(line).into_iter().map(|word| word.text).collect();
+        let name = match words.first() {
+            Some(name) => name.as_str(),
+            None => return Err(command_error("Empty command")),
+        };
+        let args = &words[1..];
+        let usage = || {
+            let &(name, usage) = COMMANDS.iter().find(|&&(n, _)| n == name).unwrap();
+            command_error(&format!("Usage: {} {}", name, usage))
+        };
+
+        let command = match name {
+            "seek" if args.len() == 1 => {
+                let arg = args[0].as_str();
+                let value = arg.trim_start_matches(['+', '-']);
+                let relative = value.len() < arg.len();
+                let (value, option) = match value.strip_suffix('%') {
+                    Some(percent) => match percent.parse::<f64>() {
+                        Ok(percent) if percent >= 0f64 && (relative || percent <= 100f64) => {
+                            let option = if relative {
+                                SeekOptions::RelativePercent
+                            } else {
+                                SeekOptions::AbsolutePercent
+                            };
+                            (percent, option)
+                        }
+                        _ => return Err(usage()),
+                    },
+                    None => {
+                        let seconds = parse_time(value).ok_or_else(usage)?;
+                        let option = if relative {
+                            SeekOptions::Relative
+                        } else {
+                            SeekOptions::Absolute
+                        };
+                        (seconds, option)
+                    }
+                };
+                if arg.starts_with('-') {
+                    Command::Seek(-value, option)
+                } else {
+                    Command::Seek(value, option)
+                }
+            }
+            "vol" | "volume" if args.len() == 1 => {
+                let (value, option) =
+                    parse_change(args[0].trim_end_matches('%')).ok_or_else(usage)?;
+                Command::Volume(value, option)
+            }
+            "speed" if args.len() == 1 => {
+                let (value, option) = parse_change(&args[0]).ok_or_else(usage)?;
+                Command::Speed(value, option)
+            }
+            "load" if args.len() == 1 || args.len() == 2 => {
+                let mode = args.get(1).map_or("append-play", String::as_str);
+                if !LOAD_MODES.contains(&mode) {
+                    return Err(usage());
+                }
+                let mut path = config::expand_tilde(&args[0]);
+                //They were completed here but mpv would look in its own working directory
+                if path.is_relative() && !args[0].contains("://") {
+                    if let Ok(dir) = env::current_dir() {
+                        path = dir.join(path);
+                    }
+                }
+                Command::Load(path, String::from(mode))
+            }
+            "loadlist" if args.len() == 1 || args.len() == 2 => {
+                let mode = args.get(1).map_or("replace", String::as_str);
+                if !LOADLIST_MODES.contains(&mode) {
+                    return Err(usage());
+                }
+                Command::LoadList(playlists::path_of(&args[0]), String::from(mode))
+            }
+            "save" if args.len() == 1 => Command::Save(playlists::path_of(&args[0])),
+            "set" if args.len() == 2 => Command::Set(args[0].clone(), args[1].clone()),
+            "raw" => {
+                //The JSON is taken as it is, without splitting it into words
+                let json = line.trim_start()[name.len()..].trim();
+                match serde_json::from_str(json) {
+                    Ok(Value::Array(command)) => {
+                        let mut object = Map::new();
+                        object.insert(String::from("command"), Value::Array(command));
+                        Command::Raw(Value::Object(object))
+                    }
+                    Ok(object @ Value::Object(_)) => Command::Raw(object),
+                    _ => return Err(usage()),
+                }
+            }
+            "quit" if args.is_empty() => Command::Quit,
+            _ if COMMANDS.iter().any(|&(n, _)| n == name) => return Err(usage()),
+            _ => return Err(command_error(&format!("Unknown command `{}`", name))),
+        };
+        Ok(command)
+    }
+
+    /// Runs the command and returns the output which should be shown, if there is any.
+    pub fn run(self, mpv: &Mpv, tags: &Mutex<Tags>) -> error::Result<Option<String>> {
+        match self {
+            Command::Seek(seconds, option) => mpv_ext::guard(|| mpv.seek(seconds, option))?,
+            Command::Volume(volume, option) => {
+                mpv_ext::guard(|| mpv.set_volume(volume, option))?
+            }
+            Command::Speed(speed, option) => mpv_ext::guard(|| mpv.set_speed(speed, option))?,
+            Command::Load(path, mode) => {
+                mpv_ext::guard(|| mpv.load_file(&path.to_string_lossy(), &mode))?
+            }
+            Command::LoadList(path, mode) => {
+                mpv_ext::guard(|| mpv.load_list(&path.to_string_lossy(), &mode))?
+            }
+            Command::Save(path) => return save(mpv, tags, &path).map(Some),
+            Command::Set(property, value) => {
+                let args = [json_escape(&property), json_escape(&value)];
+                mpv_ext::guard(|| mpv.run_command("set", &[&args[0], &args[1]]))?
+            }
+            Command::Raw(command) => {
+                return Ok(match mpv.run_raw(&command)? {
+                    Value::Null => None,
+                    Value::String(data) => Some(data),
+                    data => Some(data.to_string()),
+                })
+            }
+            Command::Quit => {}
+        }
+        Ok(None)
+    }
+}
+
+/// Returns where the word under the cursor at the end of `line` starts and the words it can
+/// be completed to. Command names are completed first, then file paths and load modes.
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    let mut words = split_words(line);
+    //A new word starts after trailing whitespace
+    if words.last().is_none_or(|word| word.end < line.len()) {
+        words.push(Word {
+            start: line.len(),
+            end: line.len(),
+            text: String::new(),
+        });
+    }
+    let index = words.len() - 1;
+    let word = &words[index];
+
+    let candidates = match (index, words[0].text.as_str()) {
+        (0, _) => COMMANDS
+            .iter()
+            .filter(|&&(name, _)| name.starts_with(&word.text))
+            .map(|&(name, _)| format!("{} ", name))
+            .collect(),
+        (1, "load") => complete_path(&word.text, Path::new(".")),
+        (1, "loadlist") | (1, "save") => complete_path(&word.text, &config::get().playlist_dir),
+        (2, "load") => complete_mode(LOAD_MODES, &word.text),
+        (2, "loadlist") => complete_mode(LOADLIST_MODES, &word.text),
+        _ => vec![],
+    };
+    (word.start, candidates)
+}
+
+/// The longest beginning all `candidates` have in common.
+pub fn common_prefix(candidates: &[String]) -> &str {
+    let first = match candidates.first() {
+        Some(first) => first.as_str(),
+        None => return "",
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|&((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, c), _)| i + c.len_utf8())
+            .min(len);
+    }
+    &first[..len]
+}
+
+fn complete_mode(modes: &[&str], text: &str) -> Vec<String> {
+    modes
+        .iter()
+        .filter(|mode| mode.starts_with(text))
+        .map(|mode| String::from(*mode))
+        .collect()
+}
+
+/// Completes paths relative to `default_dir` if `text` has no directory in it.
+fn complete_path(text: &str, default_dir: &Path) -> Vec<String> {
+    let (dir, prefix) = match text.rfind('/') {
+        Some(i) => (&text[..=i], &text[i + 1..]),
+        None => ("", text),
+    };
+    let search_dir = if dir.is_empty() {
+        default_dir.to_path_buf()
+    } else {
+        config::expand_tilde(dir)
+    };
+
+    let mut candidates: Vec<String> = match fs::read_dir(&search_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                //Hidden files only if asked for
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                    return None;
+                }
+                let is_dir = fs::metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false);
+                let path = format!("{}{}{}", dir, name, if is_dir { "/" } else { "" });
+                Some(escape(&path))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    candidates.sort();
+    candidates
+}
+
+/// Writes mpv's playlist to `path` and returns what to tell the user. The durations come from
+/// mpv for the current entry and from the tags read so far for the others.
+fn save(mpv: &Mpv, tags: &Mutex<Tags>, path: &Path) -> error::Result<String> {
+    let playlist = mpv_ext::guard(|| mpv.get_playlist())?;
+    let duration = mpv_ext::guard(|| mpv.get_property::<f64>("duration")).ok();
+    let media_title = mpv_ext::guard(|| mpv.get_property::<String>("media-title")).ok();
+    //Relative paths would be read relative to the playlist file
+    let working_dir = mpv_ext::guard(|| mpv.get_property::<String>("working-directory")).ok();
+
+    let tags = tags.lock().unwrap();
+    let items: Vec<Item> = playlist
+        .0
+        .into_iter()
+        .map(|entry| {
+            let duration = if entry.current {
+                duration
+            } else {
+                tags.peek(&entry.filename).and_then(|info| info.duration)
+            };
+            let path = match working_dir {
+                Some(ref dir) if !entry.filename.starts_with('/') &&
+                    !entry.filename.contains("://") =>
+                {
+                    Path::new(dir).join(&entry.filename).to_string_lossy().into_owned()
+                }
+                _ => entry.filename,
+            };
+            let title = if !entry.title.is_empty() {
+                Some(entry.title)
+            } else if entry.current {
+                media_title.clone()
+            } else {
+                None
+            };
+            Item {
+                path,
+                title,
+                duration,
+            }
+        })
+        .collect();
+    drop(tags);
+
+    playlists::save(path, &items).map_err(|why| {
+        command_error(&format!("Can't write {}: {}", path.display(), why))
+    })?;
+    Ok(format!("Saved {} entries to {}", items.len(), path.display()))
+}
+
+/// The command lines entered in this and earlier sessions, oldest first.
+pub struct History {
+    pub entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// Reads `$XDG_DATA_HOME/ncmpvc/history`. A missing file is an empty history.
+    pub fn load() -> History {
+        let path = config::data_dir().map(|dir| dir.join("history"));
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        History { entries, path }
+    }
+
+    /// Adds `line` as the newest entry and writes the history file.
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        self.entries.retain(|entry| entry != line);
+        self.entries.push(String::from(line));
+        if self.entries.len() > HISTORY_LENGTH {
+            let excess = self.entries.len() - HISTORY_LENGTH;
+            self.entries.drain(..excess);
+        }
+
+        match self.path {
+            Some(ref path) => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let mut content = self.entries.join("\n");
+                content.push('\n');
+                fs::write(path, content)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses `[[hh:]mm:]ss` like `get_pretty_time` writes it, the seconds may have a fraction.
+fn parse_time(text: &str) -> Option<f64> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    let mut seconds = 0f64;
+    for (i, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse().ok()?;
+        //Only the seconds may have a fraction
+        if value < 0f64 || (i + 1 < parts.len() && value.fract() != 0f64) {
+            return None;
+        }
+        seconds = seconds * 60f64 + value;
+    }
+    Some(seconds)
+}
+
+/// `50` sets a value, `+5` and `-5` change it.
+fn parse_change(text: &str) -> Option<(f64, NumberChangeOptions)> {
+    let (option, number) = if let Some(number) = text.strip_prefix('+') {
+        (NumberChangeOptions::Increase, number)
+    } else if let Some(number) = text.strip_prefix('-') {
+        (NumberChangeOptions::Decrease, number)
+    } else {
+        (NumberChangeOptions::Absolute, text)
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0f64 => Some((value, option)),
+        _ => None,
+    }
+}
+
+fn command_error(msg: &str) -> Error {
+    Error::Command(String::from(msg))
+}
+
+struct Word {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+/// Splits a command line into words like a shell does, with quotes and backslash escapes.
+fn split_words(line: &str) -> Vec<Word> {
+    let mut words = vec![];
+    let mut current: Option<Word> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            if let Some(mut word) = current.take() {
+                word.end = i;
+                words.push(word);
+            }
+            continue;
+        }
+        let word = current.get_or_insert(Word {
+            start: i,
+            end: line.len(),
+            text: String::new(),
+        });
+        match (c, quote) {
+            ('\\', Some('\'')) => word.text.push(c),
+            ('\\', _) => {
+                if let Some((_, escaped)) = chars.next() {
+                    word.text.push(escaped);
+                }
+            }
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, _) => word.text.push(c),
+        }
+    }
+    words.extend(current);
+    words
+}
+
+/// Escapes `text` so it is read back as a single word.
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_whitespace() || c == '\\' || c == '"' || c == '\'' {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+    output
+}
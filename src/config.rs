@@ -0,0 +1,515 @@
+use bindings::KeyMap;
+use browser::SortOrder;
+use columns::{Column, Width};
+use error::Error;
+use header;
+use search;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use theme::Theme;
+use toml::Spanned;
+use toml::Value;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub struct Config {
+    pub socket: Option<String>,
+    pub seek_step: f64,
+    pub fast_seek_step: f64,
+    pub volume_step: f64,
+    pub speed_step: f64,
+    pub top_bar_height: i32,
+    pub status_bar_height: i32,
+    pub message_timeout: Duration,
+    pub mouse: bool,
+    pub keys: KeyMap,
+    pub browser_root: PathBuf,
+    pub browser_show_hidden: bool,
+    pub browser_sort: SortOrder,
+    pub playlist_dir: PathBuf,
+    pub header: Vec<header::Line>,
+    //How often long lines of the top bar scroll by a cell, if they do
+    pub marquee: Option<Duration>,
+    pub columns: Vec<Column>,
+    pub column_headers: bool,
+    pub search_mode: search::Mode,
+    //Searches ignore the case unless the pattern has an upper case letter
+    pub smart_case: bool,
+    pub theme: Theme,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            socket: None,
+            seek_step: 5f64,
+            fast_seek_step: 60f64,
+            volume_step: 2f64,
+            speed_step: 0.05,
+            top_bar_height: 3,
+            status_bar_height: 3,
+            message_timeout: Duration::from_millis(2500),
+            mouse: true,
+            keys: KeyMap::default(),
+            browser_root: default_music_dir(),
+            browser_show_hidden: false,
+            browser_sort: SortOrder::Name,
+            playlist_dir: default_playlist_dir(),
+            header: header::Line::defaults(),
+            marquee: None,
+            columns: Column::defaults(),
+            column_headers: false,
+            search_mode: search::Mode::Plain,
+            smart_case: true,
+            theme: Theme::default(),
+        }
+    }
+}
+
+pub struct ConfigError {
+    path: PathBuf,
+    line: Option<usize>,
+    msg: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}: {} at line {}", self.path.display(), self.msg, line),
+            None => write!(f, "{}: {}", self.path.display(), self.msg),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    socket: Option<String>,
+    message_timeout: Option<Spanned<f64>>,
+    mouse: Option<bool>,
+    playlist_dir: Option<Spanned<String>>,
+    steps: Option<RawSteps>,
+    layout: Option<RawLayout>,
+    top_bar: Option<RawTopBar>,
+    playlist: Option<RawPlaylist>,
+    search: Option<RawSearch>,
+    keys: Option<BTreeMap<String, Spanned<Value>>>,
+    browser: Option<RawBrowser>,
+    theme: Option<Spanned<String>>,
+    themes: Option<BTreeMap<String, BTreeMap<String, Spanned<String>>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSteps {
+    seek: Option<Spanned<f64>>,
+    fast_seek: Option<Spanned<f64>>,
+    volume: Option<Spanned<f64>>,
+    speed: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLayout {
+    top_bar_height: Option<Spanned<i64>>,
+    status_bar_height: Option<Spanned<i64>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTopBar {
+    lines: Option<Vec<RawLine>>,
+    marquee: Option<bool>,
+    marquee_interval: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLine {
+    label: Option<String>,
+    format: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPlaylist {
+    columns: Option<Spanned<Vec<RawColumn>>>,
+    headers: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawColumn {
+    field: Spanned<String>,
+    width: Option<Spanned<Value>>,
+    header: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSearch {
+    mode: Option<Spanned<String>>,
+    smart_case: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBrowser {
+    root: Option<Spanned<String>>,
+    show_hidden: Option<bool>,
+    sort: Option<Spanned<String>>,
+}
+
+/// Loads the configuration file and makes it available through `get()`.
+/// Fails if the file exists but is invalid.
+pub fn init(path: Option<&str>) -> Result<(), Error> {
+    let config = load(path)?;
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// `$XDG_CONFIG_HOME/ncmpvc`, falling back to `~/.config/ncmpvc`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("ncmpvc"))
+}
+
+/// `$XDG_DATA_HOME/ncmpvc`, falling back to `~/.local/share/ncmpvc`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var("XDG_DATA_HOME") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".local/share"),
+    };
+    Some(base.join("ncmpvc"))
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Ok(home) = env::var("HOME") {
+            return PathBuf::from(format!("{}{}", home, &path[1..]));
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// `~/Music` if it exists, the home directory otherwise.
+fn default_music_dir() -> PathBuf {
+    let music = expand_tilde("~/Music");
+    if music.is_dir() {
+        music
+    } else {
+        expand_tilde("~")
+    }
+}
+
+/// `$XDG_DATA_HOME/ncmpvc/playlists`, it is created when the first playlist is saved.
+fn default_playlist_dir() -> PathBuf {
+    data_dir()
+        .map(|dir| dir.join("playlists"))
+        .unwrap_or_else(|| PathBuf::from("playlists"))
+}
+
+fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+    //An explicitly given file has to exist, the default one is optional
+    let (path, required) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match config_dir() {
+            Some(dir) => (dir.join("config.toml"), false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound && !required => {
+            return Ok(Config::default());
+        }
+        Err(why) => {
+            return Err(ConfigError {
+                path,
+                line: None,
+                msg: why.to_string(),
+            })
+        }
+    };
+
+    let raw: RawConfig = match toml::from_str(&content) {
+        Ok(raw) => raw,
+        Err(why) => {
+            //The toml error message already contains the position
+            return Err(ConfigError {
+                path,
+                line: None,
+                msg: why.to_string(),
+            });
+        }
+    };
+
+    let validator = Validator {
+        path: &path,
+        content: &content,
+    };
+    let mut config = Config {
+        socket: raw.socket,
+        mouse: raw.mouse.unwrap_or(true),
+        ..Config::default()
+    };
+
+    if let Some(dir) = raw.playlist_dir {
+        let path = expand_tilde(dir.get_ref());
+        if path.exists() && !path.is_dir() {
+            return validator.error(
+                dir.start(),
+                format!("`playlist_dir` '{}' is not a directory", path.display()),
+            );
+        }
+        config.playlist_dir = path;
+    }
+
+    if let Some(timeout) = raw.message_timeout {
+        let seconds = validator.range(timeout, "message_timeout", 0f64, 3600f64)?;
+        config.message_timeout = Duration::from_millis((seconds * 1000f64) as u64);
+    }
+
+    if let Some(steps) = raw.steps {
+        if let Some(seek) = steps.seek {
+            config.seek_step = validator.range(seek, "steps.seek", 0.01, 86400f64)?;
+        }
+        if let Some(seek) = steps.fast_seek {
+            config.fast_seek_step = validator.range(seek, "steps.fast_seek", 0.01, 86400f64)?;
+        }
+        if let Some(volume) = steps.volume {
+            config.volume_step = validator.range(volume, "steps.volume", 0.1, 100f64)?;
+        }
+        if let Some(speed) = steps.speed {
+            config.speed_step = validator.range(speed, "steps.speed", 0.01, 10f64)?;
+        }
+    }
+
+    if let Some(layout) = raw.layout {
+        if let Some(height) = layout.top_bar_height {
+            config.top_bar_height = validator.bar_height(height, "layout.top_bar_height")?;
+        }
+        if let Some(height) = layout.status_bar_height {
+            config.status_bar_height = validator.bar_height(height, "layout.status_bar_height")?;
+        }
+    }
+
+    let top_bar = raw.top_bar.unwrap_or_default();
+    let interval = match top_bar.marquee_interval {
+        Some(interval) => validator.range(interval, "top_bar.marquee_interval", 0.05, 60f64)?,
+        None => 0.3,
+    };
+    if top_bar.marquee.unwrap_or(false) {
+        config.marquee = Some(Duration::from_millis((interval * 1000f64) as u64));
+    }
+    if let Some(lines) = top_bar.lines {
+        config.header = vec![];
+        for line in lines {
+            let format = match header::Format::parse(line.format.get_ref()) {
+                Ok(format) => format,
+                Err(msg) => {
+                    return validator.error(
+                        line.format.start(),
+                        format!("invalid `top_bar.lines` format: {}", msg),
+                    )
+                }
+            };
+            config.header.push(header::Line {
+                label: line.label.unwrap_or_default(),
+                format,
+            });
+        }
+    }
+
+    if let Some(playlist) = raw.playlist {
+        if let Some(columns) = playlist.columns {
+            if columns.get_ref().is_empty() {
+                return validator.error(
+                    columns.start(),
+                    String::from("`playlist.columns` needs at least one column"),
+                );
+            }
+            config.columns = vec![];
+            for column in columns.into_inner() {
+                if column.field.get_ref().trim().is_empty() {
+                    return validator.error(
+                        column.field.start(),
+                        String::from("`playlist.columns` field must not be empty"),
+                    );
+                }
+                let width = match column.width {
+                    Some(width) => validator.column_width(width)?,
+                    None => Width::Auto,
+                };
+                config.columns.push(Column::new(column.field.get_ref(), width, column.header));
+            }
+        }
+        if let Some(headers) = playlist.headers {
+            config.column_headers = headers;
+        }
+    }
+
+    if let Some(search) = raw.search {
+        if let Some(mode) = search.mode {
+            config.search_mode = match search::Mode::from_name(mode.get_ref()) {
+                Some(mode) => mode,
+                None => {
+                    return validator.error(
+                        mode.start(),
+                        String::from("`search.mode` must be \"plain\", \"regex\" or \"fuzzy\""),
+                    )
+                }
+            };
+        }
+        if let Some(smart_case) = search.smart_case {
+            config.smart_case = smart_case;
+        }
+    }
+
+    if let Some(browser) = raw.browser {
+        if let Some(root) = browser.root {
+            let dir = expand_tilde(root.get_ref());
+            if !dir.is_dir() {
+                return validator.error(
+                    root.start(),
+                    format!("`browser.root` '{}' is not a directory", dir.display()),
+                );
+            }
+            config.browser_root = dir;
+        }
+        if let Some(show_hidden) = browser.show_hidden {
+            config.browser_show_hidden = show_hidden;
+        }
+        if let Some(sort) = browser.sort {
+            config.browser_sort = match SortOrder::from_name(sort.get_ref()) {
+                Some(sort) => sort,
+                None => {
+                    return validator.error(
+                        sort.start(),
+                        String::from("`browser.sort` must be \"name\", \"extension\" or \"modified\""),
+                    )
+                }
+            };
+        }
+    }
+
+    //User themes are checked even if they aren't used
+    let mut themes = BTreeMap::new();
+    for (name, styles) in raw.themes.unwrap_or_default() {
+        themes.insert(name, validator.theme(styles)?);
+    }
+    if let Some(name) = raw.theme {
+        config.theme = match themes.remove(name.get_ref()) {
+            Some(theme) => theme,
+            None => match Theme::builtin(name.get_ref()) {
+                Some(theme) => theme,
+                None => {
+                    return validator.error(
+                        name.start(),
+                        format!("unknown theme `{}`", name.get_ref()),
+                    )
+                }
+            },
+        };
+    }
+
+    if let Some(keys) = raw.keys {
+        config.keys = match KeyMap::new(keys) {
+            Ok(keys) => keys,
+            Err(why) => return validator.error(why.pos, why.msg),
+        };
+    }
+
+    Ok(config)
+}
+
+struct Validator<'a> {
+    path: &'a PathBuf,
+    content: &'a str,
+}
+
+impl<'a> Validator<'a> {
+    fn error<T>(&self, span_start: usize, msg: String) -> Result<T, ConfigError> {
+        Err(ConfigError {
+            path: self.path.clone(),
+            line: Some(self.content[..span_start].matches('\n').count() + 1),
+            msg,
+        })
+    }
+
+    /// Checks that `value` lies within `min` and `max`, which also rules out `inf` and `nan`.
+    /// Durations outside of it would overflow or keep a thread busy.
+    fn range(&self, value: Spanned<f64>, key: &str, min: f64, max: f64) -> Result<f64, ConfigError> {
+        if (min..=max).contains(value.get_ref()) {
+            Ok(value.into_inner())
+        } else {
+            self.error(value.start(), format!("`{}` must be between {} and {}", key, min, max))
+        }
+    }
+
+    /// Builds a user theme from its `base` theme and the styles of its elements.
+    fn theme(&self, mut styles: BTreeMap<String, Spanned<String>>) -> Result<Theme, ConfigError> {
+        let mut theme = match styles.remove("base") {
+            Some(base) => match Theme::builtin(base.get_ref()) {
+                Some(theme) => theme,
+                None => {
+                    return self.error(
+                        base.start(),
+                        format!("unknown base theme `{}`", base.get_ref()),
+                    )
+                }
+            },
+            None => Theme::default(),
+        };
+        for (element, spec) in styles {
+            if let Err(msg) = theme.set(&element, spec.get_ref()) {
+                return self.error(spec.start(), msg);
+            }
+        }
+        Ok(theme)
+    }
+
+    /// A number of cells or a percentage like `"30%"`.
+    fn column_width(&self, value: Spanned<Value>) -> Result<Width, ConfigError> {
+        let width = match *value.get_ref() {
+            Value::Integer(width) if width > 0 => Some(Width::Fixed(width as usize)),
+            Value::String(ref width) => width
+                .strip_suffix('%')
+                .and_then(|percent| percent.trim().parse().ok())
+                .filter(|percent| (1..=100).contains(percent))
+                .map(Width::Percent),
+            _ => None,
+        };
+        match width {
+            Some(width) => Ok(width),
+            None => self.error(
+                value.start(),
+                String::from("`playlist.columns` width must be a number of cells or a percentage like \"30%\""),
+            ),
+        }
+    }
+
+    fn bar_height(&self, value: Spanned<i64>, key: &str) -> Result<i32, ConfigError> {
+        let height = *value.get_ref();
+        if (3..=10).contains(&height) {
+            Ok(height as i32)
+        } else {
+            self.error(value.start(), format!("`{}` must be between 3 and 10", key))
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use config::ConfigError;
+use mpvipc;
+use ncurses::ERR;
+use std::fmt;
+use std::result;
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Everything which can go wrong while ncmpvc is running.
+pub enum Error {
+    Mpv(mpvipc::Error),
+    Curses(String),
+    Config(ConfigError),
+    Command(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mpv(ref why) => write!(f, "{}", why),
+            Error::Curses(ref why) => write!(f, "curses: {}", why),
+            Error::Config(ref why) => write!(f, "{}", why),
+            Error::Command(ref why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<mpvipc::Error> for Error {
+    fn from(why: mpvipc::Error) -> Error {
+        Error::Mpv(why)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(why: ConfigError) -> Error {
+        Error::Config(why)
+    }
+}
+
+/// Turns the return code of a curses call into a `Result`.
+pub fn curses(code: i32, call: &str) -> Result<()> {
+    if code == ERR {
+        Err(Error::Curses(format!("{} failed", call)))
+    } else {
+        Ok(())
+    }
+}
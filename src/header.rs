@@ -0,0 +1,160 @@
+use mpvipc::MpvDataType;
+use std::collections::HashMap;
+
+/// A line of the top bar: an optional label and the format of what follows it.
+pub struct Line {
+    pub label: String,
+    pub format: Format,
+}
+
+impl Line {
+    /// `Title:` and `Artist:`, the top bar before it could be configured.
+    pub fn defaults() -> Vec<Line> {
+        vec![
+            Line {
+                label: String::from("Title:"),
+                format: Format::parse("{title}").unwrap(),
+            },
+            Line {
+                label: String::from("Artist:"),
+                format: Format::parse("{artist}").unwrap(),
+            },
+        ]
+    }
+}
+
+enum Part {
+    Text(String),
+    Tag(String),
+    //Only shown if all of its own tags have a value
+    Section(Vec<Part>),
+}
+
+/// A parsed format string. `{tag}` is replaced by the value of the tag, `<...>` is left out
+/// unless all tags directly inside it have a value, `\` makes the next character literal.
+/// Square brackets are plain text, they are common around albums and years.
+pub struct Format(Vec<Part>);
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Format, String> {
+        let mut chars = format.chars();
+        let parts = parse_parts(&mut chars, false)?;
+        Ok(Format(parts))
+    }
+
+    /// Whether `tag` occurs anywhere in the format.
+    pub fn uses(&self, tag: &str) -> bool {
+        fn uses(parts: &[Part], tag: &str) -> bool {
+            parts.iter().any(|part| match *part {
+                Part::Text(_) => false,
+                Part::Tag(ref name) => name.eq_ignore_ascii_case(tag),
+                Part::Section(ref parts) => uses(parts, tag),
+            })
+        }
+        uses(&self.0, tag)
+    }
+
+    /// Fills in the tags, missing ones are left empty.
+    pub fn render<F: Fn(&str) -> Option<String>>(&self, value_of: F) -> String {
+        render_parts(&self.0, &value_of).0
+    }
+}
+
+fn parse_parts(chars: &mut std::str::Chars, in_section: bool) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None if in_section => return Err(String::from("missing `>`")),
+            None => break,
+        };
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => text.push(c),
+                None => return Err(String::from("`\\` at the end")),
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c != '{' => name.push(c),
+                        _ => return Err(String::from("missing `}`")),
+                    }
+                }
+                if name.trim().is_empty() {
+                    return Err(String::from("empty tag name in `{}`"));
+                }
+                flush(&mut text, &mut parts);
+                parts.push(Part::Tag(name));
+            }
+            '}' => return Err(String::from("`}` without `{`")),
+            '<' => {
+                flush(&mut text, &mut parts);
+                parts.push(Part::Section(parse_parts(chars, true)?));
+            }
+            '>' if in_section => break,
+            '>' => return Err(String::from("`>` without `<`")),
+            c => text.push(c),
+        }
+    }
+    flush(&mut text, &mut parts);
+    Ok(parts)
+}
+
+fn flush(text: &mut String, parts: &mut Vec<Part>) {
+    if !text.is_empty() {
+        parts.push(Part::Text(std::mem::take(text)));
+    }
+}
+
+/// Returns the text and whether all tags had a value.
+fn render_parts<F: Fn(&str) -> Option<String>>(parts: &[Part], value_of: &F) -> (String, bool) {
+    let mut output = String::new();
+    let mut complete = true;
+    for part in parts {
+        match *part {
+            Part::Text(ref text) => output.push_str(text),
+            Part::Tag(ref name) => match value_of(name) {
+                Some(value) => output.push_str(&value),
+                None => complete = false,
+            },
+            Part::Section(ref parts) => {
+                let (text, section_complete) = render_parts(parts, value_of);
+                if section_complete {
+                    output.push_str(&text);
+                }
+            }
+        }
+    }
+    (output, complete)
+}
+
+/// Looks up a tag ignoring its case, tag names differ between ID3, Vorbis comments and others.
+/// Empty values count as missing.
+pub fn tag(metadata: &HashMap<String, MpvDataType>, name: &str) -> Option<String> {
+    let value = match metadata.get(name) {
+        Some(value) => value,
+        None => metadata
+            .iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)?,
+    };
+    match *value {
+        MpvDataType::String(ref value) if !value.trim().is_empty() => Some(value.clone()),
+        MpvDataType::Usize(value) => Some(value.to_string()),
+        MpvDataType::Double(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// `44.1 kHz`
+pub fn samplerate(hz: usize) -> String {
+    format!("{} kHz", hz as f64 / 1000f64)
+}
+
+/// `320 kbps`
+pub fn bitrate(bits_per_second: f64) -> String {
+    format!("{} kbps", (bits_per_second / 1000f64).round())
+}
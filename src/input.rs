@@ -0,0 +1,1417 @@
+use bindings::Action;
+use browser::{self, Browser, Kind};
+use command::{self, Command, History};
+use config;
+use error;
+use mpv_ext::{self, MpvExt};
+use mpvipc::{self, Mpv, NumberChangeOptions, SeekOptions, Switch};
+use ncurses::*;
+use std::sync::{mpsc, Arc, Mutex};
+use messages::{Messages, Severity};
+use popup::{Pick, Popup};
+use search::{self, Query};
+use tags::Tags;
+use theme::{self, Element};
+use {get_pretty_time, jump_to_current, set_sigwinch_blocked, try_center_id, Error, Layout, Player,
+     PlaylistCanvas, Repaint, UpdateStatusBar, UpdateTopBar, View};
+
+const KEY_ENTER: i32 = 10;
+const KEY_ESC: i32 = 27;
+const KEY_BACKSPACE: i32 = 127;
+const KEY_TAB: i32 = 9;
+const KEY_CTRL_U: i32 = 21;
+const KEY_CTRL_W: i32 = 23;
+//Lines scrolled by one step of the mouse wheel
+const WHEEL_STEP: isize = 3;
+//Milliseconds the key after `ESC` may take to still count as `<M-x>`
+const META_DELAY: i32 = 25;
+
+/// Reads the keyboard and runs the bound actions on the state shared with the other threads.
+pub struct Input {
+    pub connection: Arc<Mutex<Arc<Mpv>>>,
+    pub player: Arc<Mutex<Player>>,
+    pub playlist_canvas: Arc<Mutex<PlaylistCanvas>>,
+    pub layout: Arc<Mutex<Layout>>,
+    pub view: Arc<Mutex<View>>,
+    pub browser: Arc<Mutex<Browser>>,
+    pub playlists: Arc<Mutex<Browser>>,
+    pub messages: Arc<Mutex<Messages>>,
+    pub popup: Arc<Mutex<Option<Popup>>>,
+    pub tags: Arc<Mutex<Tags>>,
+    pub history: Mutex<History>,
+    //Kept when `TAB` switches it during a search
+    pub search_mode: Mutex<search::Mode>,
+    pub tx: mpsc::Sender<Repaint>,
+}
+
+impl Input {
+    pub fn listen(&self) {
+        set_sigwinch_blocked(false);
+        let config = config::get();
+        let mut pending_keys = vec![];
+        loop {
+            let ch = getch();
+            if ch == KEY_RESIZE {
+                self.tx.send(Repaint::Resize).unwrap();
+                continue;
+            }
+            //`<M-x>` bindings get the escape if the terminal sent it along with a key
+            let cancel = ch == KEY_ESC && !(config.keys.has_meta_keys() && key_follows());
+            if cancel &&
+                (self.close_popup() || self.cancel_grab() || self.cancel_visual() ||
+                    self.clear_search())
+            {
+                pending_keys.clear();
+                continue;
+            }
+            let action = if ch == KEY_MOUSE {
+                pending_keys.clear();
+                self.mouse_input()
+            } else {
+                config.keys.feed(&mut pending_keys, ch)
+            };
+            let action = match action {
+                Some(action) => action,
+                None => continue,
+            };
+            if self.popup_input(action) {
+                continue;
+            }
+            let mpv = self.connection.lock().unwrap().clone();
+            //The status bar tells that mpv is gone, only local actions still work
+            if !mpv.is_connected() && !is_local(action, *self.view.lock().unwrap()) {
+                continue;
+            }
+            if let Err(why) = self.handle(action, &mpv) {
+                self.tx.error(&format!("Error: {}", why));
+            }
+        }
+    }
+
+    /// Selects the clicked entry, scrolls with the wheel and seeks on a click on the progress
+    /// bar. Returns the action a double click stands for.
+    fn mouse_input(&self) -> Option<Action> {
+        let mut event = MEVENT {
+            id: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            bstate: 0,
+        };
+        if getmouse(&mut event) != OK {
+            return None;
+        }
+        let state = event.bstate as i32;
+        let clicked = state & (BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED) != 0;
+        let double_clicked = state & BUTTON1_DOUBLE_CLICKED != 0;
+        let wheel = if state & BUTTON4_PRESSED != 0 {
+            -WHEEL_STEP
+        } else if state & BUTTON5_PRESSED != 0 {
+            WHEEL_STEP
+        } else {
+            0
+        };
+
+        let layout = *self.layout.lock().unwrap();
+        let height = layout.height_playlist_win;
+        if let Some(ref mut popup) = *self.popup.lock().unwrap() {
+            if wheel != 0 {
+                popup.scroll(wheel, height);
+                self.tx.send(Repaint::Popup).unwrap();
+            }
+            return None;
+        }
+
+        let view = *self.view.lock().unwrap();
+        let row = event.y - layout.playlist_y();
+        let progress_bar = layout.playlist_y() + height + layout.height_status_bar - 1;
+        if wheel != 0 {
+            match view {
+                View::Playlist => self.scroll_playlist(wheel, height as usize),
+                View::Browser | View::Playlists => {
+                    self.browser_of(view).lock().unwrap().move_selection(wheel, height as usize);
+                    self.tx.send(Repaint::Browser).unwrap();
+                }
+            }
+        } else if clicked && row >= 0 && row < height {
+            match view {
+                View::Playlist => {
+                    let line = self.playlist_canvas.lock().unwrap().top_line + row as usize;
+                    if line >= self.player.lock().unwrap().playlist.0.len() {
+                        return None;
+                    }
+                    self.playlist_canvas.lock().unwrap().selected_line = line;
+                    self.tx
+                        .send(Repaint::Playlist {
+                            clear_win: false,
+                            scroll_to_beginning: false,
+                        })
+                        .unwrap();
+                    if double_clicked {
+                        self.run(|mpv| mpv.run_command("playlist-play-index", &[&line.to_string()]));
+                    }
+                }
+                //The first line shows the directory
+                View::Browser | View::Playlists if row > 0 => {
+                    {
+                        let mut browser = self.browser_of(view).lock().unwrap();
+                        let line = browser.top_line + row as usize - 1;
+                        if line >= browser.entries.len() {
+                            return None;
+                        }
+                        browser.select(line, height as usize);
+                    }
+                    self.tx.send(Repaint::Browser).unwrap();
+                    if double_clicked {
+                        return Some(Action::Play);
+                    }
+                }
+                View::Browser | View::Playlists => {}
+            }
+        } else if clicked && event.y == progress_bar && layout.max_x > 0 {
+            if self.player.lock().unwrap().duration <= 0f64 {
+                return None;
+            }
+            let percent = f64::from(event.x) * 100f64 / f64::from(layout.max_x);
+            self.run(|mpv| mpv.seek(percent, SeekOptions::AbsolutePercent));
+        }
+        None
+    }
+
+    /// Scrolls the playlist by `offset` lines and keeps the selection visible.
+    fn scroll_playlist(&self, offset: isize, height: usize) {
+        let len = self.player.lock().unwrap().playlist.0.len();
+        {
+            let mut canvas = self.playlist_canvas.lock().unwrap();
+            let max_top = len.saturating_sub(height) as isize;
+            let top_line = std::cmp::min(std::cmp::max(canvas.top_line as isize + offset, 0), max_top);
+            canvas.top_line = top_line as usize;
+            if canvas.selected_line < canvas.top_line {
+                canvas.selected_line = canvas.top_line;
+            } else if canvas.selected_line >= canvas.top_line + height {
+                canvas.selected_line = canvas.top_line + height - 1;
+            }
+        }
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+    }
+
+    /// Runs a single mpv command outside of a key action.
+    fn run<F: FnOnce(&Mpv) -> Result<(), mpvipc::Error>>(&self, f: F) {
+        let mpv = self.connection.lock().unwrap().clone();
+        if !mpv.is_connected() {
+            return;
+        }
+        if let Err(why) = mpv_ext::guard(|| f(&mpv)) {
+            self.tx.error(&format!("Error: {}", why));
+        }
+    }
+
+    /// Scrolls, picks from or closes an open popup. Returns false if there is no popup or the
+    /// action doesn't belong to it.
+    fn popup_input(&self, action: Action) -> bool {
+        let height = self.layout.lock().unwrap().height_playlist_win;
+        let page = height as isize;
+        let pick;
+        {
+            let mut popup = self.popup.lock().unwrap();
+            let popup = match *popup {
+                Some(ref mut popup) => popup,
+                None => return false,
+            };
+            match action {
+                Action::ScrollUp => popup.move_selection(-1, height),
+                Action::ScrollDown => popup.move_selection(1, height),
+                Action::PageUp => popup.move_selection(-page, height),
+                Action::PageDown => popup.move_selection(page, height),
+                Action::ScrollToTop => popup.move_selection(isize::MIN / 2, height),
+                Action::ScrollToBottom => popup.move_selection(isize::MAX / 2, height),
+                Action::Play |
+                Action::MessageHistory |
+                Action::Help |
+                Action::Chapters |
+                Action::Tracks => {}
+                _ => return false,
+            }
+            pick = popup.pick;
+        }
+        match action {
+            Action::Play |
+            Action::MessageHistory |
+            Action::Help |
+            Action::Chapters |
+            Action::Tracks => {
+                self.close_popup();
+                if let (Action::Play, Some((pick, line))) = (action, pick) {
+                    self.pick(pick, line);
+                }
+            }
+            _ => self.tx.send(Repaint::Popup).unwrap(),
+        }
+        true
+    }
+
+    /// Runs what choosing `line` of a picker stands for.
+    fn pick(&self, pick: Pick, line: usize) {
+        match pick {
+            Pick::Chapter => self.run(|mpv| mpv.set_property("chapter", line)),
+            Pick::Track => {
+                let track = self
+                    .player
+                    .lock()
+                    .unwrap()
+                    .tracks
+                    .get(line)
+                    .map(|track| (track.property(), track.id, track.selected));
+                //Choosing the active track turns it off
+                match track {
+                    Some((property, _, true)) => {
+                        self.run(|mpv| mpv.set_property(property, String::from("no")))
+                    }
+                    Some((property, id, false)) => self.run(|mpv| mpv.set_property(property, id)),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Returns the lines the playlist actions work on and unmarks them.
+    fn take_selection(&self) -> Vec<usize> {
+        let len = self.player.lock().unwrap().playlist.0.len();
+        let mut canvas = self.playlist_canvas.lock().unwrap();
+        let lines = canvas.selection().into_iter().filter(|&line| line < len).collect();
+        if canvas.visual_start.is_some() {
+            self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+        }
+        canvas.clear_marks();
+        lines
+    }
+
+    /// Returns false if no entry was grabbed.
+    fn cancel_grab(&self) -> bool {
+        if self.playlist_canvas.lock().unwrap().grabbed.take().is_none() {
+            return false;
+        }
+        self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+        true
+    }
+
+    /// Returns false if there was no visual selection.
+    fn cancel_visual(&self) -> bool {
+        if self.playlist_canvas.lock().unwrap().visual_start.take().is_none() {
+            return false;
+        }
+        self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+        true
+    }
+
+    /// Removes the highlighting of the last search.
+    fn clear_search(&self) -> bool {
+        {
+            let mut player = self.player.lock().unwrap();
+            if player.search.take().is_none() {
+                return false;
+            }
+            player.search_results = (vec![], 0);
+        }
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+        true
+    }
+
+    /// Searches the playlist while the pattern is typed: the first match is selected and all
+    /// of them are highlighted. `ENTER` keeps the result for `search_next`, `ESC` goes back to
+    /// where the search started and `TAB` switches to the next search mode.
+    fn search(&self) {
+        let smart_case = config::get().smart_case;
+        let (top_line, bottom_line, selected_line) = {
+            let canvas = self.playlist_canvas.lock().unwrap();
+            (canvas.top_line, canvas.bottom_line, canvas.selected_line)
+        };
+        let mut pattern = String::new();
+        //Bytes of a character which hasn't been read completely
+        let mut pending = vec![];
+
+        loop {
+            let mode = *self.search_mode.lock().unwrap();
+            let prompt = {
+                let mut player = self.player.lock().unwrap();
+                let query = Query::parse(&pattern, mode, smart_case);
+                let results = match query {
+                    Ok(ref query) => {
+                        search::search(&player.playlist, &mut self.tags.lock().unwrap(), query)
+                    }
+                    Err(_) => vec![],
+                };
+                //Back to the start if nothing matches (anymore)
+                let line = results.first().cloned().unwrap_or(selected_line);
+                {
+                    let mut canvas = self.playlist_canvas.lock().unwrap();
+                    canvas.top_line = top_line;
+                    canvas.bottom_line = bottom_line;
+                    canvas.selected_line = selected_line;
+                    if let Some(new_canvas) = try_center_id(&player.playlist, &canvas, line) {
+                        *canvas = new_canvas;
+                    }
+                }
+                let label = match mode {
+                    search::Mode::Plain => String::from("Search: "),
+                    mode => format!("Search ({}): ", mode.name()),
+                };
+                let prompt = match (results.len(), &query) {
+                    (_, Err(why)) => format!("{}{}   {}", label, pattern, why),
+                    (_, Ok(query)) if query.is_empty() => format!("{}{}", label, pattern),
+                    (0, _) => format!("{}{}   no match", label, pattern),
+                    (count, _) => format!("{}{}   match 1/{}", label, pattern, count),
+                };
+                player.search = query.ok().filter(|query| !query.is_empty());
+                player.search_results = (results, 0);
+                prompt
+            };
+            self.tx.send(Repaint::StatusBar(UpdateStatusBar::Prompt(prompt))).unwrap();
+            self.tx
+                .send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                })
+                .unwrap();
+
+            match getch() {
+                KEY_ESC => {
+                    self.clear_search();
+                    let mut canvas = self.playlist_canvas.lock().unwrap();
+                    canvas.top_line = top_line;
+                    canvas.bottom_line = bottom_line;
+                    canvas.selected_line = selected_line;
+                    break;
+                }
+                KEY_ENTER => {
+                    let (count, searched) = {
+                        let player = self.player.lock().unwrap();
+                        (player.search_results.0.len(), player.search.is_some())
+                    };
+                    if let Err(why) = Query::parse(&pattern, mode, smart_case) {
+                        self.tx.send(Repaint::Message(why, Severity::Warning)).unwrap();
+                    } else if !searched {
+                        self.clear_search();
+                    } else if count == 0 {
+                        self.clear_search();
+                        self.tx
+                            .send(Repaint::Message(
+                                String::from("Search pattern not found"),
+                                Severity::Warning,
+                            ))
+                            .unwrap();
+                    } else {
+                        self.tx
+                            .send(Repaint::Message(format!("match 1/{}", count), Severity::Info))
+                            .unwrap();
+                    }
+                    break;
+                }
+                KEY_RESIZE => self.tx.send(Repaint::Resize).unwrap(),
+                KEY_BACKSPACE | ::ncurses::KEY_BACKSPACE => {
+                    pattern.pop();
+                }
+                KEY_CTRL_U => pattern.clear(),
+                KEY_TAB => {
+                    let mut mode = self.search_mode.lock().unwrap();
+                    *mode = mode.next();
+                }
+                //Printable ASCII or a byte of a UTF-8 encoded character
+                ch @ 32..=255 => {
+                    pending.push(ch as u8);
+                    match std::str::from_utf8(&pending) {
+                        Ok(c) => {
+                            pattern.push_str(c);
+                            pending.clear();
+                        }
+                        Err(ref why) if why.error_len().is_some() => pending.clear(),
+                        Err(_) => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+    }
+
+    /// Moves the entry at `from` to position `to` and selects it there. The local copy of the
+    /// playlist is changed right away so further moves don't wait for mpv's update.
+    fn move_entry(&self, mpv: &Mpv, from: usize, to: usize, height: usize) -> error::Result<()> {
+        //mpv inserts the entry before the one at the target index
+        let target = if to > from { to + 1 } else { to };
+        let moved = {
+            let player = self.player.lock().unwrap();
+            player.playlist.0.get(from).map(|entry| entry.filename.clone())
+        };
+        //Not locked while mpv is asked so a broken connection can't poison the player
+        mpv_ext::guard(|| mpv.playlist_move_id(from, target))?;
+        {
+            let mut player = self.player.lock().unwrap();
+            let entries = &mut player.playlist.0;
+            //mpv's update may have arrived in the meantime and already moved it
+            let unchanged = entries.get(from).map(|entry| &entry.filename) == moved.as_ref();
+            if unchanged && to < entries.len() {
+                let entry = entries.remove(from);
+                entries.insert(to, entry);
+                for (i, entry) in entries.iter_mut().enumerate() {
+                    entry.id = i;
+                }
+            }
+        }
+        {
+            let mut canvas = self.playlist_canvas.lock().unwrap();
+            //The marks stay with their entries
+            canvas.marked = canvas
+                .marked
+                .iter()
+                .map(|&line| match line {
+                    line if line == from => to,
+                    line if from < line && line <= to => line - 1,
+                    line if to <= line && line < from => line + 1,
+                    line => line,
+                })
+                .collect();
+            canvas.selected_line = to;
+            if to < canvas.top_line {
+                canvas.top_line = to;
+            } else if to >= canvas.top_line + height {
+                canvas.top_line = to + 1 - height;
+            }
+        }
+        self.tx
+            .send(Repaint::Playlist {
+                clear_win: false,
+                scroll_to_beginning: false,
+            })
+            .unwrap();
+        Ok(())
+    }
+
+    /// Returns false if no popup was open.
+    fn close_popup(&self) -> bool {
+        if self.popup.lock().unwrap().take().is_none() {
+            return false;
+        }
+        self.tx.send(Repaint::Popup).unwrap();
+        true
+    }
+
+    /// Reads a line on the `:` command line with history and tab completion.
+    /// Returns None if it was cancelled.
+    fn read_command_line(&self, initial: &str) -> Option<String> {
+        let history = self.history.lock().unwrap();
+        let mut line = String::from(initial);
+        //Bytes of a character which hasn't been read completely
+        let mut pending = vec![];
+        //The line being typed is kept while browsing the history
+        let mut history_index = history.entries.len();
+        let mut typed = String::new();
+        //Where the completed word starts, the candidates and the one shown
+        let mut completion: Option<(usize, Vec<String>, usize)> = None;
+
+        loop {
+            self.tx
+                .send(Repaint::StatusBar(UpdateStatusBar::Prompt(format!(":{}", line))))
+                .unwrap();
+            let ch = getch();
+            if ch != KEY_TAB && ch != KEY_BTAB {
+                completion = None;
+            }
+            match ch {
+                KEY_ESC => break,
+                KEY_ENTER if line.trim().is_empty() => break,
+                KEY_ENTER => {
+                    self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+                    return Some(line);
+                }
+                KEY_RESIZE => self.tx.send(Repaint::Resize).unwrap(),
+                KEY_BACKSPACE | ::ncurses::KEY_BACKSPACE if line.is_empty() => break,
+                KEY_BACKSPACE | ::ncurses::KEY_BACKSPACE => {
+                    line.pop();
+                }
+                KEY_CTRL_U => line.clear(),
+                KEY_CTRL_W => {
+                    let len = line.trim_end().len();
+                    line.truncate(len);
+                    let start = line
+                        .char_indices()
+                        .rev()
+                        .find(|&(_, c)| c.is_whitespace())
+                        .map_or(0, |(i, c)| i + c.len_utf8());
+                    line.truncate(start);
+                }
+                KEY_UP if history_index > 0 => {
+                    if history_index == history.entries.len() {
+                        typed = line.clone();
+                    }
+                    history_index -= 1;
+                    line = history.entries[history_index].clone();
+                }
+                KEY_DOWN if history_index < history.entries.len() => {
+                    history_index += 1;
+                    line = match history.entries.get(history_index) {
+                        Some(entry) => entry.clone(),
+                        None => typed.clone(),
+                    };
+                }
+                KEY_TAB | KEY_BTAB => {
+                    let forward = ch == KEY_TAB;
+                    if let Some((start, ref candidates, ref mut index)) = completion {
+                        *index = if forward {
+                            (*index + 1) % candidates.len()
+                        } else {
+                            (*index + candidates.len() - 1) % candidates.len()
+                        };
+                        line.truncate(start);
+                        line.push_str(&candidates[*index]);
+                        continue;
+                    }
+
+                    let (start, candidates) = command::complete(&line);
+                    let prefix = String::from(command::common_prefix(&candidates));
+                    if candidates.len() == 1 || prefix.len() > line.len() - start {
+                        line.truncate(start);
+                        line.push_str(&prefix);
+                    } else if !candidates.is_empty() {
+                        //Nothing left in common, cycle through the candidates
+                        let index = if forward { 0 } else { candidates.len() - 1 };
+                        line.truncate(start);
+                        line.push_str(&candidates[index]);
+                        completion = Some((start, candidates, index));
+                    }
+                }
+                //Printable ASCII or a byte of a UTF-8 encoded character
+                32..=255 => {
+                    pending.push(ch as u8);
+                    match std::str::from_utf8(&pending) {
+                        Ok(c) => {
+                            line.push_str(c);
+                            pending.clear();
+                        }
+                        Err(ref why) if why.error_len().is_some() => pending.clear(),
+                        Err(_) => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+        None
+    }
+
+    /// The browser shown in `view`, the saved playlists are listed by a browser too.
+    fn browser_of(&self, view: View) -> &Mutex<Browser> {
+        match view {
+            View::Playlists => &self.playlists,
+            _ => &self.browser,
+        }
+    }
+
+    fn handle(&self, action: Action, mpv: &Mpv) -> error::Result<()> {
+        let Input {
+            ref player,
+            ref playlist_canvas,
+            ref view,
+            ref tx,
+            ..
+        } = *self;
+        let config = config::get();
+        let height_playlist_win = self.layout.lock().unwrap().height_playlist_win;
+        let current_view = *view.lock().unwrap();
+        let browser = self.browser_of(current_view);
+        if current_view != View::Playlist &&
+            browser_input(action, browser, mpv, tx, height_playlist_win as usize)?
+        {
+            return Ok(());
+        }
+
+        match action {
+            Action::ScrollUp => {
+                let mut top_line = playlist_canvas.lock().unwrap().top_line;
+                let mut selected_line =
+                    playlist_canvas.lock().unwrap().selected_line;
+
+                if selected_line == top_line {
+                    top_line = top_line.saturating_sub(1);
+                }
+                selected_line = selected_line.saturating_sub(1);
+                playlist_canvas.lock().unwrap().top_line = top_line;
+                playlist_canvas.lock().unwrap().selected_line = selected_line;
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::ScrollDown => {
+                let playlist = &player.lock().unwrap().playlist;
+                let mut top_line = playlist_canvas.lock().unwrap().top_line;
+                let bottom_line = playlist_canvas.lock().unwrap().bottom_line;
+                let mut selected_line =
+                    playlist_canvas.lock().unwrap().selected_line;
+
+                if selected_line + 1 < playlist.0.len() {
+                    selected_line += 1;
+                }
+                if selected_line == bottom_line &&
+                    top_line + (height_playlist_win as usize) < playlist.0.len()
+                {
+                    top_line += 1;
+                }
+
+                playlist_canvas.lock().unwrap().top_line = top_line;
+                playlist_canvas.lock().unwrap().selected_line = selected_line;
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::PageUp => {
+                let mut top_line = playlist_canvas.lock().unwrap().top_line;
+                let mut selected_line =
+                    playlist_canvas.lock().unwrap().selected_line;
+
+                if top_line >= height_playlist_win as usize {
+                    top_line -= height_playlist_win as usize;
+                    selected_line -= height_playlist_win as usize;
+                } else {
+                    top_line = 0;
+                    selected_line = 0;
+                }
+
+                playlist_canvas.lock().unwrap().top_line = top_line;
+                playlist_canvas.lock().unwrap().selected_line = selected_line;
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::PageDown => {
+                let playlist = &player.lock().unwrap().playlist;
+                let mut top_line = playlist_canvas.lock().unwrap().top_line;
+                let mut selected_line =
+                    playlist_canvas.lock().unwrap().selected_line;
+
+                if playlist.0.len() > height_playlist_win as usize {
+                    if top_line as i32 <=
+                        playlist.0.len() as i32 - 2 * height_playlist_win
+                    {
+                        top_line += height_playlist_win as usize;
+                        selected_line += height_playlist_win as usize;
+                    } else {
+                        top_line = playlist.0.len() -
+                            height_playlist_win as usize;
+                        selected_line = playlist.0.len() - 1;
+                    }
+                } else {
+                    selected_line = playlist.0.len().saturating_sub(1);
+                }
+
+                playlist_canvas.lock().unwrap().top_line = top_line;
+                playlist_canvas.lock().unwrap().selected_line = selected_line;
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::SeekBackward => {
+                mpv_ext::guard(|| mpv.seek(-config.seek_step, SeekOptions::Relative))?;
+            }
+
+            Action::SeekForward => {
+                mpv_ext::guard(|| mpv.seek(config.seek_step, SeekOptions::Relative))?;
+            }
+
+            Action::FastSeekBackward => {
+                mpv_ext::guard(|| mpv.seek(-config.fast_seek_step, SeekOptions::Relative))?;
+            }
+
+            Action::FastSeekForward => {
+                mpv_ext::guard(|| mpv.seek(config.fast_seek_step, SeekOptions::Relative))?;
+            }
+
+            Action::SeekPercent(percent) => {
+                mpv_ext::guard(|| mpv.seek(f64::from(percent), SeekOptions::AbsolutePercent))?;
+            }
+
+            Action::Play => {
+                let selected_line =
+                    playlist_canvas.lock().unwrap().selected_line;
+                mpv_ext::guard(|| mpv.playlist_play_id(selected_line))?;
+                //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::Restart => {
+                mpv_ext::guard(|| mpv.restart())?;
+            }
+
+            Action::Next => {
+                mpv_ext::guard(|| mpv.next())?;
+            }
+
+            Action::Prev => {
+                mpv_ext::guard(|| mpv.prev())?;
+            }
+
+            Action::VolumeUp => {
+                mpv_ext::guard(|| {
+                    mpv.set_volume(config.volume_step, NumberChangeOptions::Increase)
+                })?;
+            }
+
+            Action::VolumeDown => {
+                mpv_ext::guard(|| {
+                    mpv.set_volume(config.volume_step, NumberChangeOptions::Decrease)
+                })?;
+            }
+
+            Action::SpeedUp => {
+                mpv_ext::guard(|| {
+                    mpv.set_speed(config.speed_step, NumberChangeOptions::Increase)
+                })?;
+            }
+
+            Action::SpeedDown => {
+                mpv_ext::guard(|| {
+                    mpv.set_speed(config.speed_step, NumberChangeOptions::Decrease)
+                })?;
+            }
+
+            Action::ToggleMute => {
+                mpv_ext::guard(|| mpv.set_mute(Switch::Toggle))?;
+            }
+
+            Action::SearchNext => {
+                let player = &mut player.lock().unwrap();
+
+                if !player.search_results.0.is_empty() {
+                    //Calculate new index
+                    if player.search_results.1 < player.search_results.0.len() - 1 {
+                        player.search_results.1 += 1;
+                    } else { 
+                        player.search_results.1 = 0;
+                    }
+                    let (ref results, ref current_id) = player.search_results;
+
+                    let new_canvas;
+                    {
+                        let playlist = &player.playlist;
+                        let canvas = &playlist_canvas.lock().unwrap();
+                        new_canvas = try_center_id(playlist, canvas, results[*current_id]);
+                    }
+                    if let Some(new_canvas) = new_canvas {
+                        *playlist_canvas.lock().unwrap() = new_canvas;
+                        tx.send(Repaint::Playlist {
+                            clear_win: false,
+                            scroll_to_beginning: false,
+                        }).unwrap();
+                    }
+                    tx.send(Repaint::Message(
+                        format!("match {}/{}", current_id + 1, results.len()),
+                        Severity::Info,
+                    )).unwrap();
+                }
+            }
+
+            Action::JumpToCurrent => {
+                let playlist = &player.lock().unwrap().playlist;
+                let new_canvas;
+                {
+                    let canvas = &playlist_canvas.lock().unwrap();
+                    new_canvas = jump_to_current(playlist, canvas);
+                }
+                if let Some(new_canvas) = new_canvas {
+                    *playlist_canvas.lock().unwrap() = new_canvas;
+                    tx.send(Repaint::Playlist {
+                        clear_win: false,
+                        scroll_to_beginning: false,
+                    }).unwrap();
+                }
+            }
+
+            Action::TogglePause => {
+                mpv_ext::guard(|| mpv.toggle())?;
+            }
+
+            Action::Quit => quit(),
+
+            Action::Remove => {
+                //From the bottom up so the other lines still point to the same entries
+                for line in self.take_selection().into_iter().rev() {
+                    mpv_ext::guard(|| mpv.playlist_remove_id(line))?;
+                }
+                //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
+                //tx.send(Repaint::Playlist(true)).unwrap();
+            }
+
+            Action::ToggleMark => {
+                {
+                    let len = player.lock().unwrap().playlist.0.len();
+                    let mut canvas = playlist_canvas.lock().unwrap();
+                    let line = canvas.selected_line;
+                    if line < len && !canvas.marked.remove(&line) {
+                        canvas.marked.insert(line);
+                    }
+                }
+                self.handle(Action::ScrollDown, mpv)?;
+            }
+
+            Action::Visual => {
+                let mut canvas = playlist_canvas.lock().unwrap();
+                match canvas.visual_start.take() {
+                    Some(start) => {
+                        let selected_line = canvas.selected_line;
+                        canvas.marked.extend(start.min(selected_line)..=start.max(selected_line));
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+                    }
+                    None => {
+                        canvas.visual_start = Some(canvas.selected_line);
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Prompt(String::from(
+                            "-- VISUAL --",
+                        )))).unwrap();
+                    }
+                }
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::ClearMarks => {
+                self.take_selection();
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::MoveToTop => {
+                //Every entry moves up past the unmarked ones above it
+                for (to, line) in self.take_selection().into_iter().enumerate() {
+                    if line != to {
+                        mpv_ext::guard(|| mpv.playlist_move_id(line, to))?;
+                    }
+                }
+            }
+
+            Action::MoveToBottom => {
+                let len = player.lock().unwrap().playlist.0.len();
+                //Each entry moved to the end shifts the following ones up by one
+                for (moved, line) in self.take_selection().into_iter().enumerate() {
+                    mpv_ext::guard(|| mpv.playlist_move_id(line - moved, len))?;
+                }
+            }
+
+            Action::Crop => {
+                let lines = self.take_selection();
+                let entries: Vec<(usize, bool)> = player
+                    .lock()
+                    .unwrap()
+                    .playlist
+                    .0
+                    .iter()
+                    .map(|entry| (entry.id, entry.current))
+                    .collect();
+                //The song being played is kept
+                for &(id, current) in entries.iter().rev() {
+                    if !current && lines.binary_search(&id).is_err() {
+                        mpv_ext::guard(|| mpv.playlist_remove_id(id))?;
+                    }
+                }
+            }
+
+            Action::PlayNext => {
+                let current = player
+                    .lock()
+                    .unwrap()
+                    .playlist
+                    .0
+                    .iter()
+                    .position(|entry| entry.current);
+                let mut current = match current {
+                    Some(current) => current,
+                    None => {
+                        return Err(error::Error::Command(String::from("Nothing is being played")))
+                    }
+                };
+                let (above, below): (Vec<usize>, Vec<usize>) = self
+                    .take_selection()
+                    .into_iter()
+                    .filter(|&line| line != current)
+                    .partition(|&line| line < current);
+                //The entries above the current one go right after it in reverse order, which
+                //moves the current entry up every time
+                for &line in above.iter().rev() {
+                    mpv_ext::guard(|| mpv.playlist_move_id(line, current + 1))?;
+                    current -= 1;
+                }
+                for (i, line) in below.into_iter().enumerate() {
+                    let to = current + above.len() + 1 + i;
+                    if line != to {
+                        mpv_ext::guard(|| mpv.playlist_move_id(line, to))?;
+                    }
+                }
+            }
+
+            Action::MoveUp | Action::MoveDown => {
+                let selected_line = playlist_canvas.lock().unwrap().selected_line;
+                let len = player.lock().unwrap().playlist.0.len();
+                let to = if action == Action::MoveUp {
+                    selected_line.checked_sub(1)
+                } else if selected_line + 1 < len {
+                    Some(selected_line + 1)
+                } else {
+                    None
+                };
+                if let Some(to) = to {
+                    self.move_entry(mpv, selected_line, to, height_playlist_win as usize)?;
+                }
+            }
+
+            Action::Grab => {
+                let (grabbed, selected_line) = {
+                    let canvas = playlist_canvas.lock().unwrap();
+                    (canvas.grabbed, canvas.selected_line)
+                };
+                let len = player.lock().unwrap().playlist.0.len();
+                match grabbed {
+                    Some(from) => {
+                        self.cancel_grab();
+                        if from != selected_line && from < len && selected_line < len {
+                            let height = height_playlist_win as usize;
+                            self.move_entry(mpv, from, selected_line, height)?;
+                        }
+                    }
+                    None if selected_line < len => {
+                        playlist_canvas.lock().unwrap().grabbed = Some(selected_line);
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Prompt(grab_prompt(
+                            selected_line,
+                        )))).unwrap();
+                        tx.send(Repaint::Playlist {
+                            clear_win: false,
+                            scroll_to_beginning: false,
+                        }).unwrap();
+                    }
+                    None => {}
+                }
+            }
+
+            Action::Stop => {
+                mpv_ext::guard(|| mpv.stop())?;
+                //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
+                //tx.send(Repaint::Playlist(true)).unwrap();
+            }
+
+            Action::Search => self.search(),
+
+            Action::CommandLine | Action::SeekTo => {
+                let initial = if action == Action::SeekTo { "seek " } else { "" };
+                let line = match self.read_command_line(initial) {
+                    Some(line) => line,
+                    None => return Ok(()),
+                };
+                if let Err(why) = self.history.lock().unwrap().add(&line) {
+                    tx.send(Repaint::Message(
+                        format!("Could not save the command history: {}", why),
+                        Severity::Warning,
+                    )).unwrap();
+                }
+
+                let command = Command::parse(&line)?;
+                if let Command::Quit = command {
+                    quit();
+                }
+                //The connection may have been replaced while typing
+                let mpv = self.connection.lock().unwrap().clone();
+                if !mpv.is_connected() {
+                    return Err(error::Error::Command(String::from("Not connected to mpv")));
+                }
+                let saved = matches!(command, Command::Save(_));
+                if let Some(output) = command.run(&mpv, &self.tags)? {
+                    tx.send(Repaint::Message(output, Severity::Info)).unwrap();
+                }
+                //Show the new playlist file right away
+                if saved && *view.lock().unwrap() == View::Playlists {
+                    self.playlists.lock().unwrap().refresh();
+                    tx.send(Repaint::Browser).unwrap();
+                }
+            }
+
+            Action::UpdatePlaylist => {
+                let playlist = mpv_ext::guard(|| mpv.get_playlist())?;
+                player.lock().unwrap().playlist = playlist;
+                tx.send(Repaint::Playlist {
+                    clear_win: true,
+                    scroll_to_beginning: true,
+                }).unwrap();
+            }
+
+            Action::Shuffle => {
+                mpv_ext::guard(|| mpv.run_command("playlist-shuffle", &[]))?;
+
+                let playlist = &mpv_ext::guard(|| mpv.get_playlist())?;
+                let new_canvas;
+                {
+                    let canvas = &playlist_canvas.lock().unwrap();
+                    new_canvas = jump_to_current(playlist, canvas);
+                }
+                if let Some(new_canvas) = new_canvas {
+                    *playlist_canvas.lock().unwrap() = new_canvas;
+                    tx.send(Repaint::Playlist {
+                        clear_win: false,
+                        scroll_to_beginning: false,
+                    }).unwrap();
+                }
+            }
+
+            Action::CycleLoopFile => {
+                mpv_ext::guard(|| {
+                    mpv.run_command("cycle-values", &["loop-file", "inf", "no"])
+                })?;
+            }
+
+            Action::CycleLoopPlaylist => {
+                mpv_ext::guard(|| {
+                    mpv.run_command("cycle-values", &["loop-playlist", "inf", "no"])
+                })?;
+            }
+
+            Action::ToggleConsume => {
+                let consume = {
+                    let mut player = player.lock().unwrap();
+                    player.consume = !player.consume;
+                    player.consume
+                };
+                tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                tx.send(Repaint::Message(
+                    format!("Consume mode {}", if consume { "on" } else { "off" }),
+                    Severity::Info,
+                )).unwrap();
+            }
+
+            Action::MessageHistory => {
+                let mut lines: Vec<(String, attr_t)> = self
+                    .messages
+                    .lock()
+                    .unwrap()
+                    .history()
+                    .map(|message| {
+                        (
+                            format!("{}  {}", message.time(), message.text),
+                            message.severity.attributes(),
+                        )
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    lines.push((String::from("No messages yet"), A_NORMAL()));
+                }
+                *self.popup.lock().unwrap() = Some(Popup::new("Messages", lines));
+                tx.send(Repaint::Popup).unwrap();
+            }
+
+            Action::Help => {
+                *self.popup.lock().unwrap() = Some(Popup::new("Help", config.keys.help()));
+                tx.send(Repaint::Popup).unwrap();
+            }
+
+            Action::Chapters => {
+                let popup = {
+                    let player = player.lock().unwrap();
+                    if player.chapters.is_empty() {
+                        return Err(error::Error::Command(String::from("No chapters")));
+                    }
+                    let current = player.chapter.unwrap_or(0);
+                    let lines = player
+                        .chapters
+                        .iter()
+                        .enumerate()
+                        .map(|(i, chapter)| {
+                            let attributes = if i == current {
+                                theme::attributes(Element::Current)
+                            } else {
+                                A_NORMAL()
+                            };
+                            let line = format!("{}  {}", get_pretty_time(chapter.time), chapter.title);
+                            (line, attributes)
+                        })
+                        .collect();
+                    Popup::picker("Chapters", lines, Pick::Chapter, current, height_playlist_win)
+                };
+                *self.popup.lock().unwrap() = Some(popup);
+                tx.send(Repaint::Popup).unwrap();
+            }
+
+            Action::Tracks => {
+                let popup = {
+                    let player = player.lock().unwrap();
+                    if player.tracks.is_empty() {
+                        return Err(error::Error::Command(String::from("No tracks")));
+                    }
+                    let lines = player
+                        .tracks
+                        .iter()
+                        .map(|track| {
+                            let attributes = if track.selected {
+                                theme::attributes(Element::Current)
+                            } else {
+                                A_NORMAL()
+                            };
+                            let line = format!(
+                                "{} {:<5} {:>2}  {:<4} {:<10} {}",
+                                if track.selected { "*" } else { " " },
+                                track.kind,
+                                track.id,
+                                track.lang,
+                                track.codec,
+                                track.title
+                            );
+                            (line, attributes)
+                        })
+                        .collect();
+                    let selected = player
+                        .tracks
+                        .iter()
+                        .position(|track| track.selected)
+                        .unwrap_or(0);
+                    Popup::picker("Tracks", lines, Pick::Track, selected, height_playlist_win)
+                };
+                *self.popup.lock().unwrap() = Some(popup);
+                tx.send(Repaint::Popup).unwrap();
+            }
+
+            Action::CycleAudio => {
+                mpv_ext::guard(|| mpv.run_command("cycle", &["aid"]))?;
+            }
+
+            Action::CycleSubtitles => {
+                mpv_ext::guard(|| mpv.run_command("cycle", &["sid"]))?;
+            }
+
+            Action::CycleVideo => {
+                mpv_ext::guard(|| mpv.run_command("cycle", &["vid"]))?;
+            }
+
+            Action::NextChapter => {
+                mpv_ext::guard(|| mpv.run_command("add", &["chapter", "1"]))?;
+            }
+
+            Action::PrevChapter => {
+                mpv_ext::guard(|| mpv.run_command("add", &["chapter", "-1"]))?;
+            }
+
+            Action::ToggleBrowser | Action::TogglePlaylists => {
+                let target = if action == Action::ToggleBrowser {
+                    View::Browser
+                } else {
+                    View::Playlists
+                };
+                let mut view = view.lock().unwrap();
+                //Each key switches to its own view, or back to the playlist if it's shown already
+                if *view != target && (*view == View::Playlist || target == View::Playlists) {
+                    *view = target;
+                    self.browser_of(target).lock().unwrap().refresh();
+                    tx.send(Repaint::Browser).unwrap();
+                } else {
+                    *view = View::Playlist;
+                    tx.send(Repaint::Playlist {
+                        clear_win: true,
+                        scroll_to_beginning: false,
+                    }).unwrap();
+                }
+            }
+
+            //Only used in the file browser
+            Action::Add | Action::ToggleHidden | Action::CycleSort => {}
+
+            Action::ScrollToTop => {
+                playlist_canvas.lock().unwrap().top_line = 0;
+                playlist_canvas.lock().unwrap().selected_line = 0;
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+
+            Action::ScrollToBottom => {
+                let playlist_len = player.lock().unwrap().playlist.0.len();
+                let mut canvas = playlist_canvas.lock().unwrap();
+                canvas.top_line =
+                    playlist_len.saturating_sub(height_playlist_win as usize);
+                canvas.selected_line = playlist_len.saturating_sub(1);
+                drop(canvas);
+                tx.send(Repaint::Playlist {
+                    clear_win: false,
+                    scroll_to_beginning: false,
+                }).unwrap();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn quit() -> ! {
+    endwin();
+    std::process::exit(0);
+}
+
+/// Whether another key arrives right away. It's left for the next `getch()`.
+/// Shown while the entry at `line` is grabbed.
+pub fn grab_prompt(line: usize) -> String {
+    format!("Moving entry {}: go to its new place and grab again, escape cancels", line)
+}
+
+fn key_follows() -> bool {
+    timeout(META_DELAY);
+    let next = getch();
+    timeout(-1);
+    if next == ERR {
+        return false;
+    }
+    ungetch(next);
+    true
+}
+
+/// Actions which work without mpv, e.g. while reconnecting.
+fn is_local(action: Action, view: View) -> bool {
+    match action {
+        Action::ScrollUp |
+        Action::ScrollDown |
+        Action::PageUp |
+        Action::PageDown |
+        Action::ScrollToTop |
+        Action::ScrollToBottom |
+        Action::JumpToCurrent |
+        Action::Search |
+        Action::SearchNext |
+        Action::CommandLine |
+        Action::SeekTo |
+        Action::ToggleConsume |
+        Action::ToggleMark |
+        Action::Visual |
+        Action::ClearMarks |
+        Action::MessageHistory |
+        Action::Help |
+        Action::Chapters |
+        Action::Tracks |
+        Action::ToggleBrowser |
+        Action::TogglePlaylists |
+        Action::ToggleHidden |
+        Action::CycleSort |
+        Action::Quit => true,
+        //The file browser checks the connection itself before adding files
+        Action::Play | Action::Add => view != View::Playlist,
+        _ => false,
+    }
+}
+
+/// Handles the actions which behave differently in the file browser and the list of saved
+/// playlists. Returns false if the action is not specific to the browser.
+fn browser_input(
+    action: Action,
+    browser: &Mutex<Browser>,
+    mpv: &Mpv,
+    tx: &mpsc::Sender<Repaint>,
+    height: usize,
+) -> error::Result<bool> {
+    let mut media = None;
+    let mut playlist = None;
+    {
+        let mut browser = browser.lock().unwrap();
+        match action {
+            Action::ScrollUp => browser.move_selection(-1, height),
+            Action::ScrollDown => browser.move_selection(1, height),
+            Action::PageUp => browser.move_selection(-(height as isize), height),
+            Action::PageDown => browser.move_selection(height as isize, height),
+            Action::ScrollToTop => browser.select(0, height),
+            Action::ScrollToBottom => {
+                let last = browser.entries.len();
+                browser.select(last, height)
+            }
+            Action::ToggleHidden => browser.toggle_hidden(),
+            Action::CycleSort => browser.cycle_sort(),
+            Action::Play | Action::Add => {
+                let (path, is_dir) = match browser.selected() {
+                    Some(entry) => (entry.path.clone(), entry.is_dir),
+                    None => return Ok(true),
+                };
+                if is_dir && action == Action::Play {
+                    browser.open(path, height);
+                } else if !mpv.is_connected() {
+                    return Err(error::Error::Command(String::from("Not connected to mpv")));
+                } else if browser.kind == Kind::Playlists {
+                    playlist = Some(path);
+                } else {
+                    media = Some((path, browser.show_hidden, browser.sort));
+                }
+            }
+            _ => return Ok(false),
+        }
+    }
+    tx.send(Repaint::Browser).unwrap();
+
+    if let Some(path) = playlist {
+        let mode = if action == Action::Play { "replace" } else { "append" };
+        mpv_ext::guard(|| mpv.load_list(&path.to_string_lossy(), mode))?;
+        tx.send(Repaint::Message(
+            format!(
+                "{} {}",
+                if action == Action::Play { "Loaded" } else { "Appended" },
+                path.display()
+            ),
+            Severity::Info,
+        )).unwrap();
+    }
+
+    //Read and loaded without holding the lock, so a large directory doesn't block drawing and a
+    //broken connection can't poison it
+    if let Some((path, show_hidden, sort)) = media {
+        let files = browser::collect_media(&path, show_hidden, sort);
+        let mode = if action == Action::Play { "append-play" } else { "append" };
+        for file in &files {
+            mpv_ext::guard(|| mpv.load_file(&file.to_string_lossy(), mode))?;
+        }
+        tx.send(Repaint::Message(
+            format!("Added {} file(s) to the playlist", files.len()),
+            Severity::Info,
+        )).unwrap();
+    }
+    Ok(true)
+}
@@ -7,6 +7,7 @@ macro_rules! error(
     } }
 );
 
+#[allow(unused_macros)]
 macro_rules! println_stderr(
     ($($arg:tt)*) => { {
         use ::std::io::Write;
@@ -1,15 +1,50 @@
+extern crate libc;
 extern crate ncurses;
 extern crate mpvipc;
+extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
 use ncurses::*;
 use mpvipc::*;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 mod macros;
+mod bindings;
+mod browser;
+mod cli;
+mod columns;
+mod command;
+mod config;
+mod error;
+mod header;
+mod input;
+mod messages;
+mod mpv_ext;
+mod playlists;
+mod popup;
+mod search;
+mod tags;
+mod text;
+mod theme;
+
+use browser::{Browser, Kind};
+use config::Config;
+use messages::{Message, Messages, Severity};
+use mpv_ext::MpvExt;
+use popup::Popup;
+use search::Query;
+use tags::Tags;
+use theme::Element;
 
 const OBS_ID_PLAYLIST: usize = 1;
 const OBS_ID_PAUSE: usize = 2;
@@ -19,284 +54,358 @@ const OBS_ID_METADATA: usize = 5;
 const OBS_ID_VOLUME: usize = 6;
 const OBS_ID_MUTE: usize = 7;
 const OBS_ID_SPEED: usize = 8;
-
-const KEY_ENTER: i32 = 10;
-const KEY_ESC: i32 = 27;
-const KEY_BACKSPACE: i32 = 127;
-const KEY_GT: i32 = '>' as i32;
-const KEY_LT: i32 = '<' as i32;
-const KEY_PLUS: i32 = '+' as i32;
-const KEY_MINUS: i32 = '-' as i32;
-const KEY_LSBR: i32 = '[' as i32;
-const KEY_RSBR: i32 = ']' as i32;
-const KEY_M: i32 = 'm' as i32;
-const KEY_N: i32 = 'n' as i32;
-const KEY_O: i32 = 'o' as i32;
-const KEY_P: i32 = 'p' as i32;
-const KEY_Q: i32 = 'q' as i32;
-const KEY_R: i32 = 'r' as i32;
-const KEY_S: i32 = 's' as i32;
-const KEY_U: i32 = 'u' as i32;
-const KEY_Z: i32 = 'z' as i32;
-const KEY_SLASH: i32 = '/' as i32;
+const OBS_ID_LOOP_FILE: usize = 9;
+const OBS_ID_LOOP_PLAYLIST: usize = 10;
+const OBS_ID_CHAPTER: usize = 11;
+const OBS_ID_CHAPTER_LIST: usize = 12;
+//The track list is read when one of these changes
+const OBS_ID_TRACK_COUNT: usize = 13;
+const OBS_ID_AID: usize = 14;
+const OBS_ID_SID: usize = 15;
+const OBS_ID_VID: usize = 16;
+const OBS_ID_MEDIA_TITLE: usize = 17;
+const OBS_ID_AUDIO_CODEC: usize = 18;
+const OBS_ID_SAMPLERATE: usize = 19;
+//Only observed if the top bar shows it, it changes all the time with variable bitrates
+const OBS_ID_BITRATE: usize = 20;
+
+//Waiting time between attempts to reconnect, doubled after every failure
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(250);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(8);
 
 enum Repaint {
     Playlist {
         clear_win: bool,
         scroll_to_beginning: bool,
     },
+    //Entries were removed from the playlist, holds the new lines of the old ones
+    PlaylistShrunk(Vec<Option<usize>>),
+    Browser,
+    Resize,
+    All,
     StatusBar(UpdateStatusBar),
     TopBar(UpdateTopBar),
+    Message(String, Severity),
+    Popup,
 }
 
 enum UpdateStatusBar {
+    //Removes the prompt
     Clear,
-    Time,
-    Message(String, Formatting),
+    //Time, pause, mute or connection state changed
+    Playback,
+    Prompt(String),
 }
 
 enum UpdateTopBar {
     Clear,
+    Flags,
     Metadata,
     Speed,
     Volume,
+    //Scrolls the lines which are too long by a cell
+    Marquee,
+}
+
+struct Chapter {
+    title: String,
+    //Start in seconds
+    time: f64,
 }
 
-enum Formatting {
-    Normal,
-    Blinking,
+/// An entry of mpv's `track-list`.
+struct Track {
+    id: usize,
+    //`audio`, `sub` or `video`
+    kind: String,
+    title: String,
+    lang: String,
+    codec: String,
+    selected: bool,
+}
+
+impl Track {
+    /// The property which selects tracks of this kind.
+    fn property(&self) -> &'static str {
+        match self.kind.as_str() {
+            "audio" => "aid",
+            "sub" => "sid",
+            _ => "vid",
+        }
+    }
 }
 
 struct Player {
+    audio_codec: Option<String>,
+    bitrate: Option<f64>,
+    chapter: Option<usize>,
+    chapters: Vec<Chapter>,
+    consume: bool,
     duration: f64,
+    is_connected: bool,
     is_muted: bool,
     is_paused: bool,
+    loop_file: Option<String>,
+    loop_playlist: Option<String>,
+    media_title: Option<String>,
     metadata: Option<HashMap<String, MpvDataType>>,
     playlist: Playlist,
+    samplerate: Option<usize>,
+    //Highlighted in the playlist
+    search: Option<Query>,
     search_results: (Vec<usize>, usize),
     speed: f64,
     time_pos: f64,
+    tracks: Vec<Track>,
     volume: f64,
 }
 
+impl Player {
+    /// Reads the current state from mpv after (re)connecting.
+    fn new(mpv: &Mpv) -> Result<Player, mpvipc::Error> {
+        Ok(Player {
+            audio_codec: None,
+            bitrate: None,
+            chapter: None,
+            chapters: vec![],
+            consume: false,
+            duration: 0f64,
+            is_connected: true,
+            is_muted: mpv.get_property("mute")?,
+            is_paused: mpv.get_property("pause")?,
+            loop_file: None,
+            loop_playlist: None,
+            media_title: mpv.get_property("media-title").ok(),
+            metadata: mpv.get_metadata().ok(),
+            playlist: mpv.get_playlist()?,
+            samplerate: None,
+            search: None,
+            search_results: (vec![], 0),
+            speed: mpv.get_property("speed")?,
+            time_pos: 0f64,
+            tracks: vec![],
+            volume: mpv.get_property("volume")?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Playlist,
+    Browser,
+    Playlists,
+}
+
 struct PlaylistCanvas {
     top_line: usize,
     bottom_line: usize,
     selected_line: usize,
+    //The entry being moved in grab mode
+    grabbed: Option<usize>,
+    marked: BTreeSet<usize>,
+    //Where the visual selection started, it ends at the selected line
+    visual_start: Option<usize>,
+}
+
+impl PlaylistCanvas {
+    fn is_marked(&self, line: usize) -> bool {
+        self.marked.contains(&line) ||
+            self.visual_start.is_some_and(|start| {
+                line >= start.min(self.selected_line) && line <= start.max(self.selected_line)
+            })
+    }
+
+    /// The lines the playlist actions work on in ascending order: the marked ones and the
+    /// visual selection, or the selected line if nothing is marked.
+    fn selection(&self) -> Vec<usize> {
+        let mut lines = self.marked.clone();
+        if let Some(start) = self.visual_start {
+            lines.extend(start.min(self.selected_line)..=start.max(self.selected_line));
+        }
+        if lines.is_empty() {
+            lines.insert(self.selected_line);
+        }
+        lines.into_iter().collect()
+    }
+
+    fn clear_marks(&mut self) {
+        self.marked.clear();
+        self.visual_start = None;
+    }
+
+    /// Moves the selected line, the marks and the grabbed entry along with their entries after
+    /// some were removed. Lines of removed entries are clamped to the playlist or dropped.
+    fn follow_removal(&mut self, lines: &[Option<usize>]) {
+        let line = |old: usize| lines.get(old).cloned().flatten();
+        let last = lines.iter().flatten().count().saturating_sub(1);
+        self.selected_line = line(self.selected_line).unwrap_or(self.selected_line.min(last));
+        self.top_line = self.top_line.min(self.selected_line);
+        self.visual_start = self.visual_start.map(|start| line(start).unwrap_or(start.min(last)));
+        self.marked = self.marked.iter().filter_map(|&old| line(old)).collect();
+        self.grabbed = self.grabbed.and_then(line);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Layout {
+    max_x: i32,
+    height_top_bar: i32,
+    //The line with the column headers above the playlist, if it is shown
+    height_column_headers: i32,
+    height_status_bar: i32,
+    height_playlist_win: i32,
+}
+
+impl Layout {
+    fn new(config: &Config) -> Layout {
+        let mut max_x = 0;
+        let mut max_y = 0;
+        getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+        let height_top_bar = config.top_bar_height;
+        let height_column_headers = if config.column_headers { 1 } else { 0 };
+        let height_status_bar = config.status_bar_height;
+        //Keep at least one line for the playlist on tiny terminals
+        let height_playlist_win = std::cmp::max(
+            max_y - height_status_bar - height_top_bar - height_column_headers,
+            1,
+        );
+
+        Layout {
+            max_x,
+            height_top_bar,
+            height_column_headers,
+            height_status_bar,
+            height_playlist_win,
+        }
+    }
+
+    /// Where the playlist window starts.
+    fn playlist_y(&self) -> i32 {
+        self.height_top_bar + self.height_column_headers
+    }
 }
 
 trait Error {
-    fn error(&self, &str);
+    fn error(&self, msg: &str);
 }
 
 impl Error for std::sync::mpsc::Sender<Repaint> {
     fn error(&self, msg: &str) {
-        self.send(Repaint::StatusBar(UpdateStatusBar::Message(
-            String::from(msg),
-            Formatting::Normal,
-        ))).unwrap();
-
-        std::thread::sleep(std::time::Duration::from_millis(2500));
-
-        self.send(Repaint::StatusBar(UpdateStatusBar::Clear))
+        self.send(Repaint::Message(String::from(msg), Severity::Error))
             .unwrap();
     }
 }
 
 fn main() {
+    let args = cli::parse();
+    if let Err(why) = config::init(args.config.as_deref()) {
+        error!("Error: {}", why);
+    }
+    let config = config::get();
+    let socket = args.socket(config.socket.as_ref());
+
     setlocale(LcCategory::all, "");
     initscr(); /* Start curses mode 		  */
     noecho();
     keypad(stdscr(), true);
     curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+    theme::init(&config.theme);
+    if config.mouse {
+        let events = BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED | BUTTON4_PRESSED | BUTTON5_PRESSED;
+        mousemask(events as mmask_t, None);
+    }
 
-    let mut max_x = 0;
-    let mut max_y = 0;
-    getmaxyx(stdscr(), &mut max_y, &mut max_x);
-
-    let height_top_bar = 3;
-    let height_status_bar = 3;
-    let height_playlist_win = max_y - height_status_bar - height_top_bar;
-
-    let top_bar = newwin(height_top_bar, max_x, 0, 0);
-    let playlist_win = newwin(height_playlist_win, max_x, height_status_bar, 0);
-    let status_bar = newwin(
-        height_status_bar,
-        max_x,
-        height_top_bar + height_playlist_win,
-        0,
-    );
+    //Never leave the terminal in curses mode, whichever thread panics
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        //Lost connections are handled by the caller
+        if mpv_ext::is_guarded() {
+            return;
+        }
+        endwin();
+        default_hook(info);
+        std::process::exit(101);
+    }));
+
+    //Only the input thread receives SIGWINCH so curses can report it through getch()
+    set_sigwinch_blocked(true);
+
+    let layout = Layout::new(config);
+    let (top_bar, header_win, playlist_win, status_bar) = match new_windows(&layout) {
+        Ok(windows) => windows,
+        Err(why) => {
+            endwin();
+            error!("Error: {}", why);
+        }
+    };
+    let layout_mutex = Arc::new(Mutex::new(layout));
+    let view_mutex = Arc::new(Mutex::new(View::Playlist));
+    let browser_mutex = Arc::new(Mutex::new(Browser::new(
+        Kind::Files,
+        &config.browser_root,
+        config.browser_show_hidden,
+        config.browser_sort,
+    )));
+    let playlists_mutex = Arc::new(Mutex::new(Browser::new(
+        Kind::Playlists,
+        &config.playlist_dir,
+        config.browser_show_hidden,
+        config.browser_sort,
+    )));
+
+    let messages_mutex = Arc::new(Mutex::new(Messages::default()));
+    let popup_mutex: Arc<Mutex<Option<Popup>>> = Arc::new(Mutex::new(None));
 
     let (tx, rx) = mpsc::channel();
 
-    match Mpv::connect("/tmp/mpvsocket") {
+    match Mpv::connect(&socket) {
         Ok(mpv) => {
-            let player = Arc::new(Mutex::new(Player {
-                duration: 0f64,
-                metadata: if let Ok(m) = mpv.get_metadata() {
-                    Some(m)
-                } else {
-                    None
-                },
-                is_muted: mpv.get_property("mute").unwrap(),
-                is_paused: mpv.get_property("pause").unwrap(),
-                playlist: mpv.get_playlist().unwrap(),
-                search_results: (vec![], 0),
-                speed: mpv.get_property("speed").unwrap(),
-                time_pos: 0f64,
-                volume: mpv.get_property("volume").unwrap(),
-            }));
-
-            //Spawn the playlist observation thread
+            let player = match mpv_ext::guard(|| Player::new(&mpv)) {
+                Ok(player) => Arc::new(Mutex::new(player)),
+                Err(why) => {
+                    endwin();
+                    error!("Error: Could not read the player state: {}", why);
+                }
+            };
+            //Read before the connection is shared, the tag reader needs it for relative paths
+            let working_directory = mpv_ext::guard(|| mpv.get_property("working-directory")).ok();
+            //Replaced by the connection supervisor after a reconnect. Dropping a clone of `Mpv`
+            //closes the socket for all of them, so the connection is shared through an `Arc`.
+            let connection = Arc::new(Mutex::new(Arc::new(mpv)));
+            //Kept across reconnects, the files don't change
+            let tags_mutex = Tags::new(working_directory, tx.clone());
+
+            //Spawn the connection supervisor which observes the properties and reconnects
             {
-                let (player, tx) = (player.clone(), tx.clone());
+                let (player, connection, tags, tx, socket) = (
+                    player.clone(),
+                    connection.clone(),
+                    tags_mutex.clone(),
+                    tx.clone(),
+                    socket.clone(),
+                );
                 thread::Builder::new()
-                    .name("property_observer".into())
+                    .name("connection_supervisor".into())
                     .spawn(move || {
-                        //Start a new IPC client so there are no races for events between threads
-                        let mut observer = Mpv::connect("/tmp/mpvsocket").unwrap();
-                        observer
-                            .observe_property(&OBS_ID_DURATION, "duration")
-                            .unwrap();
-                        observer
-                            .observe_property(&OBS_ID_METADATA, "metadata")
-                            .unwrap();
-                        observer.observe_property(&OBS_ID_MUTE, "mute").unwrap();
-                        observer
-                            .observe_property(&OBS_ID_PLAYLIST, "playlist")
-                            .unwrap();
-                        observer.observe_property(&OBS_ID_PAUSE, "pause").unwrap();
-                        observer.observe_property(&OBS_ID_SPEED, "speed").unwrap();
-                        observer
-                            .observe_property(&OBS_ID_TIME_POS, "time-pos")
-                            .unwrap();
-                        observer.observe_property(&OBS_ID_VOLUME, "volume").unwrap();
-                        //observer.observe_property(&999, "mpv-version").unwrap();
-                        // if !observer.get_property::<bool>("playback-abort").unwrap() {
-                        //     observer.seek(0f64, SeekOptions::Relative).unwrap();
-                        // }
+                        let mut delay = RECONNECT_DELAY_MIN;
                         loop {
-                            let event = observer.event_listen().unwrap();
-                            match event {
-                                Event::PropertyChange { id, data, .. } => {
-                                    match id { 
-                                        OBS_ID_DURATION => {
-                                            if let MpvDataType::Double(f) = data {
-                                                player.lock().unwrap().duration = f;
-                                                tx.send(Repaint::StatusBar(UpdateStatusBar::Time))
-                                                    .unwrap();
-                                            } else if let MpvDataType::Null = data {
-                                                player.lock().unwrap().duration = 0f64;
-                                                player.lock().unwrap().time_pos = 0f64;
-                                                tx.send(Repaint::StatusBar(UpdateStatusBar::Clear))
-                                                    .unwrap();
-                                            }
-                                        }
-
-                                        OBS_ID_METADATA => {
-                                            if let MpvDataType::HashMap(metadata) = data {
-                                                player.lock().unwrap().metadata = Some(metadata);
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Metadata))
-                                                    .unwrap();
-                                            } else if let MpvDataType::Null = data {
-                                                player.lock().unwrap().metadata = None;
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Clear))
-                                                    .unwrap();
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Speed))
-                                                    .unwrap();
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Volume))
-                                                    .unwrap();
-                                            }
-                                        }
-
-                                        OBS_ID_MUTE => {
-                                            if let MpvDataType::Bool(muted) = data {
-                                                player.lock().unwrap().is_muted = muted;
-                                                if muted {
-                                                    tx.send(
-                                                        Repaint::StatusBar(UpdateStatusBar::Message(
-                                                            String::from("Muted"),
-                                                            Formatting::Blinking,
-                                                        )),
-                                                    ).unwrap();
-                                                } else {
-                                                    tx.send(
-                                                        Repaint::StatusBar(UpdateStatusBar::Message(
-                                                            String::from("     "),
-                                                            Formatting::Blinking,
-                                                        )),
-                                                    ).unwrap();
-                                                }
-                                            }
-                                        }
-
-                                        OBS_ID_PAUSE => {
-                                            if let MpvDataType::Bool(paused) = data {
-                                                player.lock().unwrap().is_paused = paused;
-                                                if paused {
-                                                    tx.send(
-                                                        Repaint::StatusBar(UpdateStatusBar::Message(
-                                                            String::from("Paused"),
-                                                            Formatting::Blinking,
-                                                        )),
-                                                    ).unwrap();
-                                                } else {
-                                                    tx.send(
-                                                        Repaint::StatusBar(UpdateStatusBar::Clear),
-                                                    ).unwrap();
-                                                }
-                                            }
-                                        }
-
-                                        OBS_ID_SPEED => {
-                                            if let MpvDataType::Double(f) = data {
-                                                player.lock().unwrap().speed = f;
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Speed))
-                                                    .unwrap();
-                                            }
-                                        }
-
-                                        OBS_ID_TIME_POS => {
-                                            if let MpvDataType::Double(f) = data {
-                                                player.lock().unwrap().time_pos = f;
-                                                tx.send(Repaint::StatusBar(UpdateStatusBar::Time))
-                                                    .unwrap();
-                                            }
-                                        }
-
-                                        OBS_ID_PLAYLIST => {
-                                            if let MpvDataType::Playlist(pl) = data {
-                                                if player.lock().unwrap().playlist.0.len() !=
-                                                    pl.0.len()
-                                                {
-                                                    player.lock().unwrap().playlist = pl;
-                                                    tx.send(Repaint::Playlist {
-                                                        clear_win: true,
-                                                        scroll_to_beginning: true,
-                                                    }).unwrap();
-                                                } else {
-                                                    player.lock().unwrap().playlist = pl;
-                                                    tx.send(Repaint::Playlist {
-                                                        clear_win: false,
-                                                        scroll_to_beginning: false,
-                                                    }).unwrap();
-                                                }
-                                            }
-                                        }
-
-                                        OBS_ID_VOLUME => {
-                                            if let MpvDataType::Double(f) = data {
-                                                player.lock().unwrap().volume = f;
-                                                tx.send(Repaint::TopBar(UpdateTopBar::Volume))
-                                                    .unwrap();
-                                            }
-                                        }
-                                        _ => {}
-                                    }
+                            match mpv_ext::guard(|| connect(&socket, &player, &connection, &tags)) {
+                                Ok(mut observer) => {
+                                    delay = RECONNECT_DELAY_MIN;
+                                    tx.send(Repaint::All).unwrap();
+                                    //Returns as soon as mpv is gone
+                                    let _ = mpv_ext::guard(|| {
+                                        watch(&mut observer, &socket, &player, &tags, &tx)
+                                    });
+                                    player.lock().unwrap().is_connected = false;
+                                    tx.send(Repaint::StatusBar(UpdateStatusBar::Playback)).unwrap();
+                                }
+                                Err(_) => {
+                                    thread::sleep(delay);
+                                    delay = std::cmp::min(delay * 2, RECONNECT_DELAY_MAX);
                                 }
-                                _ => {}
                             }
-
                         }
                     })
                     .unwrap();
@@ -304,399 +413,85 @@ fn main() {
 
             let playlist_canvas = PlaylistCanvas {
                 top_line: 0,
-                bottom_line: max_y as usize,
+                bottom_line: layout.height_playlist_win as usize,
                 selected_line: 0,
+                grabbed: None,
+                marked: BTreeSet::new(),
+                visual_start: None,
             };
             let playlist_canvas_mutex = Arc::new(Mutex::new(playlist_canvas));
             {
-                let (mpv, player, playlist_canvas, tx) = (
-                    mpv.clone(),
-                    player.clone(),
-                    playlist_canvas_mutex.clone(),
-                    tx.clone(),
-                );
+                let input = input::Input {
+                    connection: connection.clone(),
+                    player: player.clone(),
+                    playlist_canvas: playlist_canvas_mutex.clone(),
+                    layout: layout_mutex.clone(),
+                    view: view_mutex.clone(),
+                    browser: browser_mutex.clone(),
+                    playlists: playlists_mutex.clone(),
+                    messages: messages_mutex.clone(),
+                    popup: popup_mutex.clone(),
+                    tags: tags_mutex.clone(),
+                    history: Mutex::new(command::History::load()),
+                    search_mode: Mutex::new(config::get().search_mode),
+                    tx: tx.clone(),
+                };
                 thread::Builder::new()
                     .name("input_listener".into())
-                    .spawn(move || {
-                        loop {
-                            let ch = getch();
-                            match ch {
-                                KEY_UP => {
-                                    let mut top_line = playlist_canvas.lock().unwrap().top_line;
-                                    let mut selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-
-                                    if selected_line == top_line {
-                                        if top_line > 0 {
-                                            top_line -= 1;
-                                        }
-                                    }
-                                    if selected_line > 0 {
-                                        selected_line -= 1;
-                                    }
-                                    playlist_canvas.lock().unwrap().top_line = top_line;
-                                    playlist_canvas.lock().unwrap().selected_line = selected_line;
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: false,
-                                        scroll_to_beginning: false,
-                                    }).unwrap();
-                                }
-
-                                KEY_DOWN => {
-                                    let ref playlist = player.lock().unwrap().playlist;
-                                    let mut top_line = playlist_canvas.lock().unwrap().top_line;
-                                    let bottom_line = playlist_canvas.lock().unwrap().bottom_line;
-                                    let mut selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-
-                                    if selected_line < playlist.0.len() - 1 {
-                                        selected_line += 1;
-                                    }
-                                    if selected_line == bottom_line {
-                                        if top_line <
-                                            playlist.0.len() - height_playlist_win as usize
-                                        {
-                                            top_line += 1;
-                                        }
-                                    }
-
-                                    playlist_canvas.lock().unwrap().top_line = top_line;
-                                    playlist_canvas.lock().unwrap().selected_line = selected_line;
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: false,
-                                        scroll_to_beginning: false,
-                                    }).unwrap();
-                                }
-
-                                KEY_PPAGE => {
-                                    let mut top_line = playlist_canvas.lock().unwrap().top_line;
-                                    let mut selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-
-                                    if top_line >= height_playlist_win as usize {
-                                        top_line -= height_playlist_win as usize;
-                                        selected_line -= height_playlist_win as usize;
-                                    } else {
-                                        top_line = 0;
-                                        selected_line = 0;
-                                    }
-
-                                    playlist_canvas.lock().unwrap().top_line = top_line;
-                                    playlist_canvas.lock().unwrap().selected_line = selected_line;
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: false,
-                                        scroll_to_beginning: false,
-                                    }).unwrap();
-                                }
-
-                                KEY_NPAGE => {
-                                    let ref playlist = player.lock().unwrap().playlist;
-                                    let mut top_line = playlist_canvas.lock().unwrap().top_line;
-                                    let mut selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-
-                                    if playlist.0.len() > height_playlist_win as usize {
-                                        if top_line as i32 <=
-                                            playlist.0.len() as i32 - 2 * height_playlist_win
-                                        {
-                                            top_line += height_playlist_win as usize;
-                                            selected_line += height_playlist_win as usize;
-                                        } else {
-                                            top_line = playlist.0.len() -
-                                                height_playlist_win as usize;
-                                            selected_line = playlist.0.len() - 1;
-                                        }
-                                    } else {
-                                        selected_line = playlist.0.len() - 1;
-                                    }
-
-                                    playlist_canvas.lock().unwrap().top_line = top_line;
-                                    playlist_canvas.lock().unwrap().selected_line = selected_line;
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: false,
-                                        scroll_to_beginning: false,
-                                    }).unwrap();
-                                }
-
-                                KEY_LEFT => {
-                                    if let Err(why) = mpv.seek(-5.0, SeekOptions::Relative) {
-                                        tx.error(&format!("Error: {}", why));
-                                    }
-                                }
-
-                                KEY_RIGHT => {
-                                    if let Err(why) = mpv.seek(5.0, SeekOptions::Relative) {
-                                        tx.error(&format!("Error: {}", why));
-                                    }
-                                }
-
-                                KEY_ENTER => {
-                                    let selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-                                    mpv.playlist_play_id(selected_line as usize).expect(
-                                        "playlist_play_id",
-                                    );
-                                    //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: false,
-                                        scroll_to_beginning: false,
-                                    }).unwrap();
-                                }
-
-                                KEY_BACKSPACE => {
-                                    mpv.restart().expect("next");
-                                }
-
-                                KEY_GT => {
-                                    mpv.next().expect("next");
-                                }
-
-                                KEY_LT => {
-                                    mpv.prev().expect("prev");
-                                }
-
-                                KEY_PLUS => {
-                                    mpv.set_volume(2f64, NumberChangeOptions::Increase).expect(
-                                        "vol_up",
-                                    );
-                                }
-                                
-                                KEY_MINUS => {
-                                    mpv.set_volume(2f64, NumberChangeOptions::Decrease).expect(
-                                        "vol_down",
-                                    );
-                                }
-                                
-                                KEY_RSBR => {
-                                    mpv.set_speed(0.05, NumberChangeOptions::Increase).expect(
-                                        "speed_up",
-                                    );
-                                }
-                                
-                                KEY_LSBR => {
-                                    mpv.set_speed(0.05, NumberChangeOptions::Decrease).expect(
-                                        "speed_down",
-                                    );
-                                }
-                                
-                                KEY_M => {
-                                    mpv.set_mute(Switch::Toggle).expect("next");
-                                }
-                                
-                                KEY_N => {
-                                    let ref mut player = player.lock().unwrap();
-
-                                    if player.search_results.0.len() > 0 {
-                                        //Calculate new index
-                                        if player.search_results.1 < player.search_results.0.len() - 1 {
-                                            player.search_results.1 += 1;
-                                        } else { 
-                                            player.search_results.1 = 0;
-                                        }
-                                        let (ref results, ref current_id) = player.search_results;
-
-                                        let new_canvas;
-                                        {
-                                            let ref playlist = player.playlist;
-                                            let ref canvas = playlist_canvas.lock().unwrap();
-                                            new_canvas = try_center_id(playlist, canvas, results[*current_id]);
-                                        }
-                                        if let Some(new_canvas) = new_canvas {
-                                            *playlist_canvas.lock().unwrap() = new_canvas;
-                                            tx.send(Repaint::Playlist {
-                                                clear_win: false,
-                                                scroll_to_beginning: false,
-                                            }).unwrap();
-                                        }
-                                    }
-                                }
-                                
-                                KEY_O => {
-                                    let ref playlist = player.lock().unwrap().playlist;
-                                    let new_canvas;
-                                    {
-                                        let ref canvas = playlist_canvas.lock().unwrap();
-                                        new_canvas = jump_to_current(playlist, canvas);
-                                    }
-                                    if let Some(new_canvas) = new_canvas {
-                                        *playlist_canvas.lock().unwrap() = new_canvas;
-                                        tx.send(Repaint::Playlist {
-                                            clear_win: false,
-                                            scroll_to_beginning: false,
-                                        }).unwrap();
-                                    }
-                                }
-                                
-                                KEY_P => {
-                                    mpv.toggle().expect("toggle");
-                                }
-                                
-                                KEY_Q => {
-                                    endwin();
-                                    //mpv.disconnect();
-                                    std::process::exit(0);
-                                }
-                                
-                                KEY_R => {
-                                    let selected_line =
-                                        playlist_canvas.lock().unwrap().selected_line;
-                                    mpv.playlist_remove_id(selected_line).unwrap();
-                                    //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
-                                    //tx.send(Repaint::Playlist(true)).unwrap();
-                                }
-                                
-                                KEY_S => {
-                                    mpv.stop().expect("mpv_stop");
-                                    //*playlist.lock().unwrap() = mpv.get_playlist().unwrap();
-                                    //tx.send(Repaint::Playlist(true)).unwrap();
-                                }
-                                
-                                KEY_SLASH => {
-                                    tx.send(
-                                        Repaint::StatusBar(UpdateStatusBar::Message(
-                                            format!("Search:"),
-                                            Formatting::Normal,
-                                        )),
-                                    ).unwrap();
-                                    let mut search_string = String::new();
-                                    loop {
-                                        let ch = getch();
-                                        match ch {
-                                            KEY_ESC => {
-                                                tx.send(Repaint::StatusBar(UpdateStatusBar::Clear))
-                                                    .unwrap();
-                                                break;
-                                            }
-
-                                            KEY_ENTER => {
-                                                let result;
-                                                if &search_string == ""
-                                                {
-                                                    result = (vec![], 0);
-                                                    tx.send(
-                                                    Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
-                                                } else {
-                                                    let ref playlist = player.lock().unwrap().playlist;
-                                                    result =
-                                                        (search_playlist(playlist, &search_string), 0);
-                                                        let match_count = result.0.len();
-                                                                                                
-                                                    if match_count as i32 > 0 {
-                                                        //Jump to first result
-                                                        let new_canvas;
-                                                        {
-                                                            let ref canvas =
-                                                                playlist_canvas.lock().unwrap();
-                                                            new_canvas =
-                                                                try_center_id(playlist,
-                                                                canvas,
-                                                                result.0[result.1]);
-                                                        }
-                                                        if let Some(new_canvas) = new_canvas {
-                                                            *playlist_canvas.lock().unwrap() =
-                                                                new_canvas;
-                                                            tx.send(Repaint::Playlist {
-                                                                clear_win: false,
-                                                                scroll_to_beginning: false,
-                                                            }).unwrap();
-
-                                                            //Clear search info
-                                                            tx.send(
-                                                    Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
-                                                        }
-                                                    } else {
-                                                        tx.send(Repaint::StatusBar(UpdateStatusBar::Message(
-                                                            String::from("Search pattern not found"),
-                                                            Formatting::Normal))).unwrap();
-                                                    }
-                                                }
-                                                player.lock().unwrap().search_results = result;
-
-                                                break;
-                                            }
-
-                                            KEY_BACKSPACE => {
-                                                search_string.pop();
-                                                tx.send(
-                                                    Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
-                                                tx.send(
-                                                    Repaint::StatusBar(UpdateStatusBar::Message(
-                                                        format!("Search: {}", search_string),
-                                                        Formatting::Normal,
-                                                    )),
-                                                ).unwrap();
-                                            }
-
-                                            _ => {
-                                                //panic!("{}", ch);
-                                                search_string.push(
-                                                    std::char::from_u32(ch as u32)
-                                                        .expect("Invalid char"),
-                                                );
-                                                tx.send(
-                                                    Repaint::StatusBar(UpdateStatusBar::Message(
-                                                        format!("Search: {}", search_string),
-                                                        Formatting::Normal,
-                                                    )),
-                                                ).unwrap();
-                                            }
-                                        }
-                                    }
-                                }
-                                
-                                KEY_U => {
-                                    player.lock().unwrap().playlist = mpv.get_playlist().unwrap();
-                                    tx.send(Repaint::Playlist {
-                                        clear_win: true,
-                                        scroll_to_beginning: true,
-                                    }).unwrap();
-                                }
-                                
-                                KEY_Z => {
-                                    mpv.run_command("playlist-shuffle", &[]).unwrap();
-
-                                    let ref playlist = mpv.get_playlist().unwrap();
-                                    let new_canvas;
-                                    {
-                                        let ref canvas = playlist_canvas.lock().unwrap();
-                                        new_canvas = jump_to_current(playlist, canvas);
-                                    }
-                                    if let Some(new_canvas) = new_canvas {
-                                        *playlist_canvas.lock().unwrap() = new_canvas;
-                                        tx.send(Repaint::Playlist {
-                                            clear_win: false,
-                                            scroll_to_beginning: false,
-                                        }).unwrap();
-                                    }
-                                }
-                                _ => {
-                                    //panic!("{}", ch);
-                                }
-                            }
-                        }
-                    })
+                    .spawn(move || input.listen())
                     .unwrap();
             }
 
-            print_status(status_bar, "This is a test");
-            wrefresh(status_bar);
-
             //Trigger first update
             tx.send(Repaint::Playlist {
                 clear_win: false,
                 scroll_to_beginning: false,
             }).unwrap();
 
+            //The search prompt, shown instead of messages
+            let mut prompt: Option<String> = None;
+            //How far the long lines of the top bar have scrolled and when they scroll next
+            let mut marquee_offset = 0;
+            let mut marquee_title: Option<String> = None;
+            let mut next_scroll: Option<Instant> = None;
+
             //Main loop
             loop {
-                wmove(top_bar, 2, 0);
-                whline(top_bar, ACS_HLINE(), max_x);
+                let Layout {
+                    max_x,
+                    height_top_bar,
+                    height_playlist_win,
+                    ..
+                } = *layout_mutex.lock().unwrap();
+                print_top_bar_line(
+                    top_bar,
+                    height_top_bar - 1,
+                    max_x,
+                    &player.lock().unwrap(),
+                );
                 wrefresh(top_bar);
                 wmove(status_bar, 0, 0);
                 whline(status_bar, ACS_HLINE(), max_x);
                 wrefresh(status_bar);
-                //Wait for repaint trigger
-                match rx.recv().unwrap() {
+                //Wait for repaint trigger or until the current message expires
+                let next_expiry = messages_mutex.lock().unwrap().next_expiry();
+                let deadline = match (next_expiry, next_scroll) {
+                    (Some(expiry), Some(scroll)) => Some(std::cmp::min(expiry, scroll)),
+                    (expiry, scroll) => expiry.or(scroll),
+                };
+                let repaint = match deadline {
+                    Some(deadline) => {
+                        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                            Ok(repaint) => repaint,
+                            Err(_) if next_scroll.is_some_and(|scroll| scroll <= Instant::now()) => {
+                                Repaint::TopBar(UpdateTopBar::Marquee)
+                            }
+                            Err(_) => Repaint::StatusBar(UpdateStatusBar::Playback),
+                        }
+                    }
+                    None => rx.recv().unwrap(),
+                };
+                match repaint {
                     //Repaint playlist
                     Repaint::Playlist {
                         clear_win,
@@ -704,91 +499,182 @@ fn main() {
                     } => {
                         let mut top_line = playlist_canvas_mutex.lock().unwrap().top_line;
                         let mut selected_line = playlist_canvas_mutex.lock().unwrap().selected_line;
-                        let ref playlist = player.lock().unwrap().playlist;
-                        //panic!("Playlist changed");
-                        if clear_win {
+                        let player = player.lock().unwrap();
+                        let playlist = &player.playlist;
+                        if clear_win && *view_mutex.lock().unwrap() == View::Playlist {
                             wclear(playlist_win);
                         }
 
                         if scroll_to_beginning {
                             top_line = 0;
                             selected_line = 0;
-                            playlist_canvas_mutex.lock().unwrap().top_line = top_line;
-                            playlist_canvas_mutex.lock().unwrap().selected_line = selected_line;
+                            let mut canvas = playlist_canvas_mutex.lock().unwrap();
+                            canvas.top_line = top_line;
+                            canvas.selected_line = selected_line;
+                            //The marked lines don't point to the same entries anymore, neither do
+                            //the start of the visual selection and the grabbed entry
+                            canvas.marked.clear();
+                            let visual_start = canvas.visual_start.take();
+                            let grabbed = canvas.grabbed.take();
+                            if visual_start.is_some() || grabbed.is_some() {
+                                tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
+                            }
                         }
 
                         let bottom_line = top_line + height_playlist_win as usize;
                         playlist_canvas_mutex.lock().unwrap().bottom_line = bottom_line;
 
-                        wmove(playlist_win, 0, 0);
                         if selected_line as i32 > playlist.0.len() as i32 - 1 {
                             selected_line = 0;
                             playlist_canvas_mutex.lock().unwrap().selected_line = selected_line;
                         }
 
-                        print_playlist(
-                            &playlist_win,
-                            &playlist,
-                            &playlist_canvas_mutex.lock().unwrap(),
-                        );
+                        //The playlist keeps changing in the background while browsing
+                        if *view_mutex.lock().unwrap() == View::Playlist {
+                            if let Some(header_win) = header_win {
+                                print_column_headers(header_win);
+                            }
+                            wmove(playlist_win, 0, 0);
+                            print_playlist(
+                                &playlist_win,
+                                playlist,
+                                &playlist_canvas_mutex.lock().unwrap(),
+                                &mut tags_mutex.lock().unwrap(),
+                                player.search.as_ref(),
+                            );
+                            if let Some(ref popup) = *popup_mutex.lock().unwrap() {
+                                popup::print_popup(playlist_win, popup);
+                            }
+                        }
                     }
 
-                    Repaint::StatusBar(what) => {
-                        match what {
-                            UpdateStatusBar::Clear => {
-                                wclear(status_bar);
+                    Repaint::PlaylistShrunk(lines) => {
+                        let mut canvas = playlist_canvas_mutex.lock().unwrap();
+                        let grabbed = canvas.grabbed;
+                        canvas.follow_removal(&lines);
+                        //The prompt names the line of the grabbed entry
+                        match canvas.grabbed {
+                            None if grabbed.is_some() => {
+                                tx.send(Repaint::StatusBar(UpdateStatusBar::Clear)).unwrap();
                             }
-                            UpdateStatusBar::Message(msg, formatting) => {
-                                match formatting {
-                                    Formatting::Normal => {}
-                                    Formatting::Blinking => {
-                                        wattron(status_bar, A_BLINK());
-                                    }
-                                }
-                                wmove(status_bar, 1, 0);
-                                wprintw(status_bar, &msg);
-                                match formatting {
-                                    Formatting::Normal => {}
-                                    Formatting::Blinking => {
-                                        wattroff(status_bar, A_BLINK());
-                                    }
-                                }
+                            Some(line) if grabbed != Some(line) => {
+                                tx.send(Repaint::StatusBar(UpdateStatusBar::Prompt(
+                                    input::grab_prompt(line),
+                                ))).unwrap();
                             }
-                            UpdateStatusBar::Time => {
-                                let player = player.lock().unwrap();
-                                let percentage = 100f64 / player.duration * player.time_pos;
-                                let time_text = &format!(
-                                    "    {} / {} ({}%)",
-                                    get_pretty_time(player.time_pos),
-                                    get_pretty_time(player.duration),
-                                    percentage as i32
-                                );
-                                //Aligned on the right
-                                wmove(status_bar, 1, max_x - time_text.len() as i32);
-                                //wmove(status_bar, 0, 0);
-                                wprintw(status_bar, time_text);
-
-                                wmove(status_bar, 2, 0);
-                                for i in 0..max_x {
-                                    if i < max_x / 100 * percentage as i32 {
-                                        wprintw(status_bar, "=");
-                                    //waddch(status_bar, '=' as u32);
-                                    //wattron(status_bar, A_STANDOUT());
-                                    } else if i == max_x / 100 * percentage as i32 {
-                                        wprintw(status_bar, ">");
-                                    //waddch(status_bar, 'ç' as u32);
-                                    //wattron(status_bar, A_STANDOUT());
-                                    } else {
-                                        wprintw(status_bar, "-");
-                                        //waddch(status_bar, ACS_HLINE());
-                                        //wattroff(status_bar, A_STANDOUT());
-                                    }
-                                }
+                            _ => {}
+                        }
+                        tx.send(Repaint::Playlist {
+                            clear_win: true,
+                            scroll_to_beginning: false,
+                        }).unwrap();
+                    }
+
+                    Repaint::Browser => {
+                        let browser = match *view_mutex.lock().unwrap() {
+                            View::Browser => Some(&browser_mutex),
+                            View::Playlists => Some(&playlists_mutex),
+                            View::Playlist => None,
+                        };
+                        if let Some(browser) = browser {
+                            if let Some(header_win) = header_win {
+                                werase(header_win);
+                                wrefresh(header_win);
+                            }
+                            browser::print_browser(playlist_win, &browser.lock().unwrap());
+                            if let Some(ref popup) = *popup_mutex.lock().unwrap() {
+                                popup::print_popup(playlist_win, popup);
                             }
                         }
-                        wmove(status_bar, 0, 0);
-                        whline(status_bar, ACS_HLINE(), max_x);
-                        wrefresh(status_bar);
+                    }
+
+                    Repaint::Popup => match *popup_mutex.lock().unwrap() {
+                        Some(ref popup) => popup::print_popup(playlist_win, popup),
+                        //Uncover the window below
+                        None => {
+                            tx.send(Repaint::Playlist {
+                                clear_win: true,
+                                scroll_to_beginning: false,
+                            }).unwrap();
+                            tx.send(Repaint::Browser).unwrap();
+                        }
+                    },
+
+                    Repaint::Resize => {
+                        let layout = Layout::new(config);
+                        *layout_mutex.lock().unwrap() = layout;
+
+                        if let Err(why) =
+                            resize_windows(&layout, top_bar, header_win, playlist_win, status_bar)
+                        {
+                            tx.error(&format!("Error: {}", why));
+                        }
+                        wclear(stdscr());
+                        wrefresh(stdscr());
+
+                        //Keep the selected lines visible in the resized window
+                        for browser in &[&browser_mutex, &playlists_mutex] {
+                            let mut browser = browser.lock().unwrap();
+                            let selected_line = browser.selected_line;
+                            browser.select(selected_line, layout.height_playlist_win as usize);
+                        }
+                        {
+                            let playlist_len = player.lock().unwrap().playlist.0.len();
+                            let mut canvas = playlist_canvas_mutex.lock().unwrap();
+                            let height = layout.height_playlist_win as usize;
+                            if canvas.selected_line >= canvas.top_line + height {
+                                canvas.top_line = canvas.selected_line + 1 - height;
+                            } else if canvas.selected_line < canvas.top_line {
+                                canvas.top_line = canvas.selected_line;
+                            }
+                            if canvas.top_line + height > playlist_len {
+                                canvas.top_line = playlist_len.saturating_sub(height);
+                            }
+                            canvas.bottom_line = canvas.top_line + height;
+                        }
+
+                        tx.send(Repaint::All).unwrap();
+                    }
+
+                    //Repaint all panes from the current player state
+                    Repaint::All => {
+                        let player = player.lock().unwrap();
+                        tx.send(Repaint::Playlist {
+                            clear_win: true,
+                            scroll_to_beginning: false,
+                        }).unwrap();
+                        tx.send(Repaint::Browser).unwrap();
+                        tx.send(Repaint::TopBar(UpdateTopBar::Clear)).unwrap();
+                        if player.metadata.is_some() || player.media_title.is_some() {
+                            tx.send(Repaint::TopBar(UpdateTopBar::Metadata)).unwrap();
+                        }
+                        tx.send(Repaint::TopBar(UpdateTopBar::Speed)).unwrap();
+                        tx.send(Repaint::TopBar(UpdateTopBar::Volume)).unwrap();
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback)).unwrap();
+                        tx.send(Repaint::Popup).unwrap();
+                    }
+
+                    Repaint::StatusBar(what) => {
+                        match what {
+                            UpdateStatusBar::Clear => prompt = None,
+                            UpdateStatusBar::Playback => {}
+                            UpdateStatusBar::Prompt(text) => prompt = Some(text),
+                        }
+                        print_status_bar(
+                            status_bar,
+                            &player.lock().unwrap(),
+                            prompt.as_deref(),
+                            messages_mutex.lock().unwrap().current(),
+                        );
+                    }
+
+                    Repaint::Message(text, severity) => {
+                        messages_mutex.lock().unwrap().push(
+                            text,
+                            severity,
+                            config.message_timeout,
+                        );
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback)).unwrap();
                     }
 
                     Repaint::TopBar(what) => {
@@ -796,150 +682,842 @@ fn main() {
                             UpdateTopBar::Clear => {
                                 wclear(top_bar);
                             }
-                            UpdateTopBar::Metadata => {
+                            //The width of speed and volume decides how much room the lines
+                            //on their left have, so all of them are drawn together
+                            UpdateTopBar::Metadata |
+                            UpdateTopBar::Speed |
+                            UpdateTopBar::Volume |
+                            UpdateTopBar::Marquee => {
                                 let player = player.lock().unwrap();
-                                let ref metadata = player.metadata.as_ref().unwrap();
-                                wmove(top_bar, 0, 0);
-                                wprintw(top_bar, "Title:  ");
-                                if metadata.contains_key("title") {
-                                    if let MpvDataType::String(ref title) = metadata["title"] {
-                                        wprintw(top_bar, title);
-                                    }
-                                } else {
-                                    wprintw(
-                                        top_bar,
-                                        if let Ok(ref title) = mpv.get_property::<String>(
-                                            "media-title",
-                                        )
-                                        {
-                                            title
-                                        } else {
-                                            "<empty>"
-                                        },
-                                    );
-                                }
-
-                                wmove(top_bar, 1, 0);
-                                wprintw(top_bar, "Artist: ");
-                                if metadata.contains_key("artist") {
-                                    if let MpvDataType::String(ref artist) = metadata["artist"] {
-                                        wprintw(top_bar, artist);
-                                    }
-                                } else {
-                                    wprintw(top_bar, "<empty>");
+                                if let UpdateTopBar::Marquee = what {
+                                    marquee_offset += 1;
+                                } else if player.media_title != marquee_title {
+                                    //A new song starts from the beginning
+                                    marquee_offset = 0;
+                                    marquee_title = player.media_title.clone();
                                 }
+                                let overflows =
+                                    print_top_bar(top_bar, height_top_bar - 1, &player, marquee_offset);
+                                next_scroll = match config.marquee {
+                                    Some(interval) if overflows => match next_scroll {
+                                        Some(scroll) if scroll > Instant::now() => Some(scroll),
+                                        _ => Some(Instant::now() + interval),
+                                    },
+                                    _ => None,
+                                };
                             }
 
-                            UpdateTopBar::Speed => {
-                                let player = player.lock().unwrap();
-                                let speed_str = &format!("  Speed: {:.*} ", 2, player.speed);
+                            //Drawn together with the separator line below
+                            UpdateTopBar::Flags => {}
+                        }
 
-                                //Aligned on the right
-                                wmove(top_bar, 0, max_x - speed_str.len() as i32);
-                                wprintw(top_bar, speed_str);
-                            }
+                        print_top_bar_line(
+                            top_bar,
+                            height_top_bar - 1,
+                            max_x,
+                            &player.lock().unwrap(),
+                        );
+                        wrefresh(top_bar);
+                    }
+                }
+            }
+        }
+        Err(code) => {
+            endwin();
+            error!("Error: Could not connect to mpv socket '{}': {}", socket, code);
+        }
+    }
+}
 
-                            UpdateTopBar::Volume => {
-                                let player = player.lock().unwrap();
-                                let volume_str = &format!("  Volume: {}%%", player.volume as usize);
+/// Opens the event and command connections to mpv and reads the current player state and the
+/// working directory.
+/// Returns the connection used for observing properties.
+fn connect(
+    socket: &str,
+    player: &Mutex<Player>,
+    connection: &Mutex<Arc<Mpv>>,
+    tags: &Mutex<Tags>,
+) -> Result<Mpv, mpvipc::Error> {
+    //Start a new IPC client so there are no races for events between threads
+    let observer = Mpv::connect(socket)?;
+    let mpv = Mpv::connect(socket)?;
+    let mut state = Player::new(&mpv)?;
+    {
+        let mut player = player.lock().unwrap();
+        //Consume mode is a client-side setting and survives reconnects
+        state.consume = player.consume;
+        *player = state;
+    }
+    //Nothing else uses this connection yet
+    let working_directory = mpv.get_property("working-directory").ok();
+    tags.lock().unwrap().set_working_directory(working_directory);
+    *connection.lock().unwrap() = Arc::new(mpv);
+    Ok(observer)
+}
+
+/// The lines the entries of `before` have in `after` if entries were only removed. mpvipc numbers
+/// the entries by their position, so they are told apart by their file names.
+fn remaining_lines(before: &Playlist, after: &Playlist) -> Option<Vec<Option<usize>>> {
+    if after.0.len() >= before.0.len() {
+        return None;
+    }
+    let mut next = 0;
+    let lines = before
+        .0
+        .iter()
+        .map(|entry| {
+            if after.0.get(next).is_some_and(|kept| kept.filename == entry.filename) {
+                next += 1;
+                Some(next - 1)
+            } else {
+                None
+            }
+        })
+        .collect();
+    if next == after.0.len() { Some(lines) } else { None }
+}
+
+/// Observes the player properties and keeps `player` up to date until the connection is lost.
+fn watch(
+    observer: &mut Mpv,
+    socket: &str,
+    player: &Mutex<Player>,
+    tags: &Mutex<Tags>,
+    tx: &mpsc::Sender<Repaint>,
+) -> Result<(), mpvipc::Error> {
+    observer.observe_property(&OBS_ID_DURATION, "duration")?;
+    observer.observe_property(&OBS_ID_METADATA, "metadata")?;
+    observer.observe_property(&OBS_ID_MUTE, "mute")?;
+    observer.observe_property(&OBS_ID_PLAYLIST, "playlist")?;
+    observer.observe_property(&OBS_ID_PAUSE, "pause")?;
+    observer.observe_property(&OBS_ID_SPEED, "speed")?;
+    observer.observe_property(&OBS_ID_TIME_POS, "time-pos")?;
+    observer.observe_property(&OBS_ID_VOLUME, "volume")?;
+    observer.observe_property(&OBS_ID_LOOP_FILE, "loop-file")?;
+    observer.observe_property(&OBS_ID_LOOP_PLAYLIST, "loop-playlist")?;
+    observer.observe_property(&OBS_ID_CHAPTER, "chapter")?;
+    observer.observe_property(&OBS_ID_CHAPTER_LIST, "chapter-list")?;
+    observer.observe_property(&OBS_ID_TRACK_COUNT, "track-list/count")?;
+    observer.observe_property(&OBS_ID_AID, "aid")?;
+    observer.observe_property(&OBS_ID_SID, "sid")?;
+    observer.observe_property(&OBS_ID_VID, "vid")?;
+    observer.observe_property(&OBS_ID_MEDIA_TITLE, "media-title")?;
+    observer.observe_property(&OBS_ID_AUDIO_CODEC, "audio-codec-name")?;
+    observer.observe_property(&OBS_ID_SAMPLERATE, "audio-params/samplerate")?;
+    if header_uses("bitrate") {
+        observer.observe_property(&OBS_ID_BITRATE, "audio-bitrate")?;
+    }
+    //Consume mode removes entries through its own client
+    let commander = Mpv::connect(socket)?;
+    let mut last_current: Option<(usize, String)> = None;
+
+    loop {
+        let event = match observer.event_listen() {
+            Ok(event) => event,
+            //Events mpvipc can't parse are skipped as long as mpv is still there
+            Err(why) => {
+                if observer.is_connected() {
+                    continue;
+                }
+                return Err(why);
+            }
+        };
+        if let Event::PropertyChange { id, data, .. } = event {
+            match id {
+                OBS_ID_DURATION => {
+                    if let MpvDataType::Double(f) = data {
+                        player.lock().unwrap().duration = f;
+                        if let Some(filename) = current_filename(&player.lock().unwrap()) {
+                            tags.lock().unwrap().set_duration(&filename, f);
+                            tx.send(Repaint::Playlist {
+                                clear_win: false,
+                                scroll_to_beginning: false,
+                            }).unwrap();
+                        }
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback))
+                            .unwrap();
+                    } else if let MpvDataType::Null = data {
+                        player.lock().unwrap().duration = 0f64;
+                        player.lock().unwrap().time_pos = 0f64;
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback))
+                            .unwrap();
+                    }
+                }
+
+                OBS_ID_METADATA => {
+                    if let MpvDataType::HashMap(metadata) = data {
+                        let mut player = player.lock().unwrap();
+                        if let Some(filename) = current_filename(&player) {
+                            tags.lock().unwrap().set_metadata(&filename, &metadata);
+                            tx.send(Repaint::Playlist {
+                                clear_win: false,
+                                scroll_to_beginning: false,
+                            }).unwrap();
+                        }
+                        player.metadata = Some(metadata);
+                        tx.send(Repaint::TopBar(UpdateTopBar::Metadata))
+                            .unwrap();
+                    } else if let MpvDataType::Null = data {
+                        player.lock().unwrap().metadata = None;
+                        tx.send(Repaint::TopBar(UpdateTopBar::Metadata))
+                            .unwrap();
+                    }
+                }
+
+                OBS_ID_MUTE => {
+                    if let MpvDataType::Bool(muted) = data {
+                        player.lock().unwrap().is_muted = muted;
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback))
+                            .unwrap();
+                    }
+                }
 
-                                //Aligned on the right
-                                wmove(top_bar, 1, max_x - volume_str.len() as i32);
-                                wprintw(top_bar, volume_str);
+                OBS_ID_PAUSE => {
+                    if let MpvDataType::Bool(paused) = data {
+                        player.lock().unwrap().is_paused = paused;
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback))
+                            .unwrap();
+                    }
+                }
+
+                OBS_ID_SPEED => {
+                    if let MpvDataType::Double(f) = data {
+                        player.lock().unwrap().speed = f;
+                        tx.send(Repaint::TopBar(UpdateTopBar::Speed))
+                            .unwrap();
+                    }
+                }
+
+                OBS_ID_TIME_POS => {
+                    if let MpvDataType::Double(f) = data {
+                        player.lock().unwrap().time_pos = f;
+                        tx.send(Repaint::StatusBar(UpdateStatusBar::Playback))
+                            .unwrap();
+                    }
+                }
+
+                OBS_ID_LOOP_FILE => {
+                    player.lock().unwrap().loop_file = loop_value(data);
+                    tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                }
+
+                OBS_ID_LOOP_PLAYLIST => {
+                    player.lock().unwrap().loop_playlist = loop_value(data);
+                    tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                }
+
+                OBS_ID_CHAPTER => {
+                    player.lock().unwrap().chapter = match data {
+                        MpvDataType::Usize(chapter) => Some(chapter),
+                        _ => None,
+                    };
+                    tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                }
+
+                OBS_ID_CHAPTER_LIST => {
+                    player.lock().unwrap().chapters = match data {
+                        MpvDataType::Array(list) => list.iter().filter_map(chapter_of).collect(),
+                        _ => vec![],
+                    };
+                    tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                }
+
+                OBS_ID_TRACK_COUNT | OBS_ID_AID | OBS_ID_SID | OBS_ID_VID => {
+                    player.lock().unwrap().tracks = read_tracks(&commander);
+                    tx.send(Repaint::TopBar(UpdateTopBar::Flags)).unwrap();
+                }
+
+                OBS_ID_MEDIA_TITLE => {
+                    player.lock().unwrap().media_title = match data {
+                        MpvDataType::String(title) => Some(title),
+                        _ => None,
+                    };
+                    tx.send(Repaint::TopBar(UpdateTopBar::Metadata)).unwrap();
+                }
+
+                OBS_ID_AUDIO_CODEC => {
+                    player.lock().unwrap().audio_codec = match data {
+                        MpvDataType::String(codec) => Some(codec),
+                        _ => None,
+                    };
+                    if header_uses("codec") {
+                        tx.send(Repaint::TopBar(UpdateTopBar::Metadata)).unwrap();
+                    }
+                }
+
+                OBS_ID_SAMPLERATE => {
+                    player.lock().unwrap().samplerate = match data {
+                        MpvDataType::Usize(samplerate) => Some(samplerate),
+                        MpvDataType::Double(samplerate) => Some(samplerate as usize),
+                        _ => None,
+                    };
+                    if header_uses("samplerate") {
+                        tx.send(Repaint::TopBar(UpdateTopBar::Metadata)).unwrap();
+                    }
+                }
+
+                OBS_ID_BITRATE => {
+                    let bitrate = match data {
+                        MpvDataType::Usize(bitrate) => Some(bitrate as f64),
+                        MpvDataType::Double(bitrate) => Some(bitrate),
+                        _ => None,
+                    };
+                    //Redrawing for changes that don't show would only flicker
+                    let mut player = player.lock().unwrap();
+                    if player.bitrate.map(header::bitrate) != bitrate.map(header::bitrate) {
+                        tx.send(Repaint::TopBar(UpdateTopBar::Metadata)).unwrap();
+                    }
+                    player.bitrate = bitrate;
+                }
+
+                OBS_ID_PLAYLIST => {
+                    if let MpvDataType::Playlist(pl) = data {
+                        //The entry which was current before has finished
+                        let current = pl.0
+                            .iter()
+                            .find(|entry| entry.current)
+                            .map(|entry| (entry.id, entry.filename.clone()));
+                        if let Some((id, filename)) = last_current.take() {
+                            let changed = match current {
+                                Some((_, ref f)) => *f != filename,
+                                None => true,
+                            };
+                            if changed && player.lock().unwrap().consume {
+                                consume_entry(&commander, &pl, id, &filename, tx);
                             }
                         }
+                        last_current = current;
+
+                        let mut player = player.lock().unwrap();
+                        let resized = player.playlist.0.len() != pl.0.len();
+                        //Consume mode keeps removing entries, the marks and the selection follow
+                        //them instead of starting over
+                        let lines = remaining_lines(&player.playlist, &pl);
+                        player.playlist = pl;
+                        if let Some(lines) = lines {
+                            tx.send(Repaint::PlaylistShrunk(lines)).unwrap();
+                        } else if resized {
+                            tx.send(Repaint::Playlist {
+                                clear_win: true,
+                                scroll_to_beginning: true,
+                            }).unwrap();
+                        } else {
+                            tx.send(Repaint::Playlist {
+                                clear_win: false,
+                                scroll_to_beginning: false,
+                            }).unwrap();
+                        }
+                    }
+                }
 
-                        wmove(top_bar, 2, 0);
-                        whline(top_bar, ACS_HLINE(), max_x);
-                        wrefresh(top_bar);
+                OBS_ID_VOLUME => {
+                    if let MpvDataType::Double(f) = data {
+                        player.lock().unwrap().volume = f;
+                        tx.send(Repaint::TopBar(UpdateTopBar::Volume))
+                            .unwrap();
                     }
                 }
+                _ => {}
             }
         }
-        Err(code) => {
-            endwin();
-            error!("Error: Could not connect to mpv socket: {}", code);
+    }
+}
+
+fn current_filename(player: &Player) -> Option<String> {
+    player
+        .playlist
+        .0
+        .iter()
+        .find(|entry| entry.current)
+        .map(|entry| entry.filename.clone())
+}
+
+/// Creates the top bar, the column headers if they are shown, the playlist window and the
+/// status bar.
+fn new_windows(layout: &Layout) -> error::Result<(WINDOW, Option<WINDOW>, WINDOW, WINDOW)> {
+    let new_window = |height, y| {
+        let win = newwin(height, layout.max_x, y, 0);
+        if win.is_null() {
+            Err(error::Error::Curses(String::from("newwin failed")))
+        } else {
+            Ok(win)
+        }
+    };
+    let header_win = if layout.height_column_headers > 0 {
+        Some(new_window(layout.height_column_headers, layout.height_top_bar)?)
+    } else {
+        None
+    };
+    Ok((
+        new_window(layout.height_top_bar, 0)?,
+        header_win,
+        new_window(layout.height_playlist_win, layout.playlist_y())?,
+        new_window(
+            layout.height_status_bar,
+            layout.playlist_y() + layout.height_playlist_win,
+        )?,
+    ))
+}
+
+/// Fits the windows into the new layout after the terminal has been resized.
+fn resize_windows(
+    layout: &Layout,
+    top_bar: WINDOW,
+    header_win: Option<WINDOW>,
+    playlist_win: WINDOW,
+    status_bar: WINDOW,
+) -> error::Result<()> {
+    error::curses(wresize(top_bar, layout.height_top_bar, layout.max_x), "wresize")?;
+    if let Some(header_win) = header_win {
+        error::curses(
+            wresize(header_win, layout.height_column_headers, layout.max_x),
+            "wresize",
+        )?;
+    }
+    error::curses(
+        wresize(playlist_win, layout.height_playlist_win, layout.max_x),
+        "wresize",
+    )?;
+    error::curses(mvwin(playlist_win, layout.playlist_y(), 0), "mvwin")?;
+    error::curses(
+        wresize(status_bar, layout.height_status_bar, layout.max_x),
+        "wresize",
+    )?;
+    error::curses(
+        mvwin(status_bar, layout.playlist_y() + layout.height_playlist_win, 0),
+        "mvwin",
+    )
+}
+
+/// Draws the prompt, the newest message or the playback state on the left, the playback time on
+/// the right and the progress bar below.
+fn print_status_bar(win: WINDOW, player: &Player, prompt: Option<&str>, message: Option<&Message>) {
+    let max_x = getmaxx(win);
+    let height = getmaxy(win);
+    werase(win);
+    wmove(win, 0, 0);
+    whline(win, ACS_HLINE(), max_x);
+
+    let mut time_text = String::new();
+    if player.is_connected && player.duration > 0f64 {
+        let percentage = 100f64 / player.duration * player.time_pos;
+        time_text = format!(
+            "    {} / {} ({}%)",
+            get_pretty_time(player.time_pos),
+            get_pretty_time(player.duration),
+            percentage as i32
+        );
+        //Aligned on the right, left out if the terminal is too narrow for it
+        if time_text.len() <= max_x as usize {
+            wmove(win, 1, max_x - time_text.len() as i32);
+            waddstr(win, &time_text);
+        } else {
+            time_text.clear();
+        }
+
+        wmove(win, height - 1, 0);
+        let position = (f64::from(max_x) * percentage / 100f64) as i32;
+        wattrset(win, theme::attributes(Element::ProgressFilled));
+        for i in 0..max_x {
+            if i < position {
+                waddstr(win, "=");
+            } else if i == position {
+                waddstr(win, ">");
+                wattrset(win, theme::attributes(Element::ProgressEmpty));
+            } else {
+                waddstr(win, "-");
+            }
+        }
+        wattrset(win, A_NORMAL());
+    }
+
+    let (text, attributes) = if let Some(prompt) = prompt {
+        (prompt, A_NORMAL())
+    } else if let Some(message) = message {
+        (message.text.as_str(), message.severity.attributes())
+    } else if !player.is_connected {
+        ("Disconnected – retrying", A_BOLD())
+    } else if player.is_paused {
+        ("Paused", A_BLINK())
+    } else if player.is_muted {
+        ("Muted", A_BLINK())
+    } else {
+        ("", A_NORMAL())
+    };
+    let width = std::cmp::max((max_x as usize).saturating_sub(time_text.len()), 1);
+    wmove(win, 1, 0);
+    wattron(win, attributes);
+    waddstr(win, &text::truncate(text, width));
+    wattroff(win, attributes);
+    wrefresh(win);
+}
+
+/// Draws the separator line below the top bar with the current chapter on the left and the
+/// active playback modes on the right.
+fn print_top_bar_line(win: WINDOW, y: i32, max_x: i32, player: &Player) {
+    wmove(win, y, 0);
+    whline(win, ACS_HLINE(), max_x);
+
+    let mut flags = vec![];
+    if let Some(ref value) = player.loop_file {
+        flags.push(format!("[loop-file {}]", value));
+    }
+    if let Some(ref value) = player.loop_playlist {
+        flags.push(format!("[loop-playlist {}]", value));
+    }
+    if player.consume {
+        flags.push(String::from("[consume]"));
+    }
+    //Only worth showing if there is a choice
+    let audio_tracks: Vec<&Track> = player.tracks.iter().filter(|t| t.kind == "audio").collect();
+    if audio_tracks.len() > 1 {
+        if let Some((i, track)) = audio_tracks.iter().enumerate().find(|&(_, t)| t.selected) {
+            let mut flag = format!("[audio {}/{}", i + 1, audio_tracks.len());
+            if !track.lang.is_empty() {
+                flag.push(' ');
+                flag.push_str(&track.lang);
+            }
+            flag.push(']');
+            flags.push(flag);
+        }
+    }
+    if let Some(track) = player.tracks.iter().find(|t| t.kind == "sub" && t.selected) {
+        flags.push(if track.lang.is_empty() {
+            format!("[sub {}]", track.id)
+        } else {
+            format!("[sub {}]", track.lang)
+        });
+    }
+    let flags = if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" {} ", flags.join(" "))
+    };
+    if !flags.is_empty() {
+        wmove(win, y, std::cmp::max(max_x - text::width(&flags) as i32 - 1, 0));
+        wattron(win, A_BOLD());
+        waddstr(win, &flags);
+        wattroff(win, A_BOLD());
+    }
+
+    let chapter = player
+        .chapter
+        .and_then(|i| player.chapters.get(i).map(|chapter| (i, chapter)));
+    if let Some((i, chapter)) = chapter {
+        let mut text = format!(" Chapter {}/{}", i + 1, player.chapters.len());
+        if !chapter.title.is_empty() {
+            text.push_str(": ");
+            text.push_str(&chapter.title);
         }
+        //Leave room for the flags
+        let width = max_x as usize - std::cmp::min(text::width(&flags) + 4, max_x as usize);
+        wmove(win, y, 1);
+        print_label(win, &text::truncate(&text, width));
+        waddstr(win, " ");
     }
 }
 
-fn print_playlist(win: &WINDOW, playlist: &Playlist, canvas: &PlaylistCanvas) {
+/// Draws the lines above the separator line: the configured ones on the left, speed and volume
+/// on the right. A line without any text shows `<empty>`, one which doesn't fit into the room
+/// left is cut off or scrolled by `marquee_offset` cells if the marquee is on. Returns whether
+/// a line doesn't fit.
+fn print_top_bar(win: WINDOW, height: i32, player: &Player, marquee_offset: usize) -> bool {
+    let config = config::get();
+    let max_x = getmaxx(win);
+    let lines = &config.header;
+    let label_width = lines.iter().map(|line| text::width(&line.label)).max().unwrap_or(0);
+    //Nothing is playing
+    let is_idle = player.metadata.is_none() && player.media_title.is_none();
+    let mut overflows = false;
+    for y in 0..height {
+        wmove(win, y, 0);
+        wclrtoeol(win);
+        let info = match y {
+            0 => Some(("Speed:", format!(" {:.*} ", 2, player.speed))),
+            1 => Some(("Volume:", format!(" {}% ", player.volume as usize))),
+            _ => None,
+        };
+        let info_width = info.as_ref().map_or(0, |&(label, ref value)| label.len() + value.len() + 2);
+
+        if let Some(line) = lines.get(y as usize).filter(|_| !is_idle) {
+            if label_width > 0 {
+                print_label(win, &line.label);
+                waddstr(win, &" ".repeat(label_width + 1 - text::width(&line.label)));
+            }
+            let mut text = line.format.render(|tag| header_value(player, tag));
+            if text.is_empty() {
+                text = String::from("<empty>");
+            }
+            let room = std::cmp::max(max_x - getcurx(win) - info_width as i32, 0) as usize;
+            if text::width(&text) > room {
+                overflows = true;
+                text = match config.marquee {
+                    Some(_) => text::scroll(&text, marquee_offset, room),
+                    None => text::truncate(&text, room),
+                };
+            }
+            waddstr(win, &text);
+        }
+
+        //Aligned on the right
+        if let Some((label, value)) = info {
+            wmove(win, y, std::cmp::max(max_x - info_width as i32, 0));
+            waddstr(win, "  ");
+            print_label(win, label);
+            waddstr(win, &value);
+        }
+    }
+    overflows
+}
+
+/// The value of a tag in the top bar. `title` falls back to mpv's media title, which is
+/// the file name if there is no title tag.
+fn header_value(player: &Player, tag: &str) -> Option<String> {
+    let tag = tag.to_lowercase();
+    match tag.as_str() {
+        "codec" => return player.audio_codec.clone(),
+        "samplerate" => return player.samplerate.map(header::samplerate),
+        "bitrate" => return player.bitrate.map(header::bitrate),
+        _ => {}
+    }
+    let value = player.metadata.as_ref().and_then(|metadata| header::tag(metadata, &tag));
+    if value.is_none() && tag == "title" {
+        return player.media_title.clone();
+    }
+    value
+}
+
+/// Whether one of the top bar lines shows `tag`.
+fn header_uses(tag: &str) -> bool {
+    config::get().header.iter().any(|line| line.format.uses(tag))
+}
+
+fn print_label(win: WINDOW, label: &str) {
+    wattrset(win, theme::attributes(Element::Label));
+    waddstr(win, label);
+    wattrset(win, A_NORMAL());
+}
+
+/// Returns the value of `loop-file` or `loop-playlist` if looping is enabled.
+fn loop_value(data: MpvDataType) -> Option<String> {
+    match data {
+        MpvDataType::Bool(true) => Some(String::from("inf")),
+        MpvDataType::String(ref s) if s != "no" => Some(s.clone()),
+        MpvDataType::Usize(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads an entry of `chapter-list`.
+fn chapter_of(data: &MpvDataType) -> Option<Chapter> {
+    let entry = match *data {
+        MpvDataType::HashMap(ref entry) => entry,
+        _ => return None,
+    };
+    let time = match entry.get("time") {
+        Some(&MpvDataType::Double(time)) => time,
+        Some(&MpvDataType::Usize(time)) => time as f64,
+        _ => return None,
+    };
+    let title = match entry.get("title") {
+        Some(MpvDataType::String(title)) => title.clone(),
+        _ => String::new(),
+    };
+    Some(Chapter { title, time })
+}
+
+/// Reads `track-list`, which is empty if nothing is playing.
+fn read_tracks(mpv: &Mpv) -> Vec<Track> {
+    let list = match mpv.get_json_property("track-list") {
+        Ok(serde_json::Value::Array(list)) => list,
+        _ => return vec![],
+    };
+    let text = |track: &serde_json::Value, key| {
+        track
+            .get(key)
+            .and_then(serde_json::Value::as_str)
+            .map_or_else(String::new, String::from)
+    };
+    list.iter()
+        .filter_map(|track| {
+            Some(Track {
+                id: track.get("id")?.as_u64()? as usize,
+                kind: text(track, "type"),
+                title: text(track, "title"),
+                lang: text(track, "lang"),
+                codec: text(track, "codec"),
+                selected: track
+                    .get("selected")
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+/// Removes the finished entry `filename` which was at position `id` before the playlist changed.
+fn consume_entry(
+    mpv: &Mpv,
+    playlist: &Playlist,
+    id: usize,
+    filename: &str,
+    tx: &mpsc::Sender<Repaint>,
+) {
+    //Prefer the occurrence closest to the old position in case a file is queued twice
+    let position = playlist
+        .0
+        .iter()
+        .enumerate()
+        .filter(|&(_, entry)| entry.filename == filename && !entry.current)
+        .min_by_key(|&(i, _)| (i as isize - id as isize).abs())
+        .map(|(i, _)| i);
+    if let Some(position) = position {
+        if let Err(why) = mpv.playlist_remove_id(position) {
+            tx.error(&format!("Error: {}", why));
+        }
+    }
+}
+
+fn set_sigwinch_blocked(blocked: bool) {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGWINCH);
+        let how = if blocked { libc::SIG_BLOCK } else { libc::SIG_UNBLOCK };
+        libc::pthread_sigmask(how, &set, std::ptr::null_mut());
+    }
+}
+
+fn print_playlist(
+    win: &WINDOW,
+    playlist: &Playlist,
+    canvas: &PlaylistCanvas,
+    tags: &mut Tags,
+    search: Option<&Query>,
+) {
     let from = canvas.top_line;
     let to = canvas.bottom_line;
     let selected = canvas.selected_line;
-    let max_x = getmaxx(*win);
-    if playlist.0.len() > 0 {
-        let mut y = 0;
-        for i in from..to {
-            wmove(*win, y, 0);
+    let columns = &config::get().columns;
+    let widths = column_widths(getmaxx(*win));
+    let read_tags = columns.iter().any(|column| column.field.needs_tags());
+    if !playlist.0.is_empty() {
+        for (y, i) in (from..to).enumerate() {
+            wmove(*win, y as i32, 0);
             if i < playlist.0.len() {
-                let ref entry = playlist.0[i as usize];
+                let entry = &playlist.0[i];
+                let mut attributes = if entry.current {
+                    theme::attributes(Element::Current)
+                } else {
+                    A_NORMAL()
+                };
+                if canvas.is_marked(i) {
+                    attributes = theme::merge(attributes, theme::attributes(Element::Marked));
+                }
+                if canvas.grabbed == Some(i) {
+                    attributes = theme::merge(attributes, theme::attributes(Element::Grabbed));
+                }
                 if i == selected {
-                    wattron(*win, A_REVERSE());
+                    attributes = theme::merge(attributes, theme::attributes(Element::Selected));
                 }
-                let mut output = format!(
-                    "{}{}{}",
-                    entry.id,
-                    if entry.current {
-                        if entry.id < 10 {
-                            "   ▶ "
-                        } else if entry.id < 100 {
-                            "  ▶ "
-                        } else {
-                            " ▶ "
-                        }
-                    } else {
-                        if entry.id < 10 {
-                            "     "
-                        } else if entry.id < 100 {
-                            "    "
+                let marker_attributes = if i == selected {
+                    attributes
+                } else {
+                    theme::attributes(Element::Marker)
+                };
+
+                let info = if read_tags {
+                    tags.get(&entry.filename)
+                } else {
+                    tags.peek(&entry.filename)
+                };
+                wattrset(*win, attributes);
+                for (n, (column, &width)) in columns.iter().zip(&widths).enumerate() {
+                    if n > 0 {
+                        waddstr(*win, " ");
+                    }
+                    let cell = column.cell(&column.field.text(entry, info), width);
+                    let matches = search.map_or(vec![], |search| search.highlights(&column.field, &cell));
+                    print_highlighted(*win, &cell, &matches, attributes);
+                    //The marker goes right before the separator line after the first column
+                    if n == 0 {
+                        if entry.current {
+                            wattrset(*win, marker_attributes);
+                            waddstr(*win, "▶");
+                            wattrset(*win, attributes);
                         } else {
-                            "   "
+                            waddstr(*win, " ");
                         }
-                    },
-                    if &entry.title == "" {
-                        &entry.filename
-                    } else {
-                        &entry.title
-                    }
-                );
-                let len = output.chars().count();
-                if len > max_x as usize {
-                    output.truncate(max_x as usize - 3);
-                    output.push_str("...");
-                }
-                if entry.current {
-                    wattron(*win, A_BOLD());
-                }
-                if len < max_x as usize {
-                    for _ in 0..max_x as usize - len {
-                        output.push(' ');
                     }
                 }
-                wprintw(*win, &output);
-                //printw("\n");
-                wattroff(*win, A_BOLD());
-                if i == selected {
-                    wattroff(*win, A_REVERSE());
-                }
+                //Fill the line so the selection and the marks reach the edge
+                let rest = std::cmp::max(getmaxx(*win) - getcurx(*win), 0) as usize;
+                waddstr(*win, &" ".repeat(rest));
+                wattrset(*win, A_NORMAL());
             }
-            y += 1;
         }
     } else {
-        wmove(*win, 0, 6);
+        wmove(*win, 0, widths[0] as i32 + 2);
         wprintw(*win, "Playlist is empty");
     }
 
-    wmove(*win, 0, 5);
+    wmove(*win, 0, widths[0] as i32 + 1);
     wvline(*win, ACS_VLINE(), (to - from) as i32);
     wrefresh(*win);
 }
 
+/// Draws `text` with the byte ranges in `matches` highlighted.
+fn print_highlighted(win: WINDOW, text: &str, matches: &[std::ops::Range<usize>], attributes: attr_t) {
+    let mut end = 0;
+    for range in matches {
+        waddstr(win, &text[end..range.start]);
+        wattrset(win, theme::merge(attributes, theme::attributes(Element::Match)));
+        waddstr(win, &text[range.clone()]);
+        wattrset(win, attributes);
+        end = range.end;
+    }
+    waddstr(win, &text[end..]);
+}
+
+/// The widths of the playlist columns. The first one is followed by the marker and the
+/// separator line, the others are separated by a space.
+fn column_widths(max_x: i32) -> Vec<usize> {
+    let columns = &config::get().columns;
+    columns::widths(columns, max_x as usize, std::cmp::max(columns.len(), 2))
+}
+
+/// Draws the column headers, aligned like the columns of the playlist.
+fn print_column_headers(win: WINDOW) {
+    let columns = &config::get().columns;
+    let widths = column_widths(getmaxx(win));
+    werase(win);
+    wmove(win, 0, 0);
+    wattrset(win, theme::attributes(Element::Label));
+    for (n, (column, &width)) in columns.iter().zip(&widths).enumerate() {
+        match n {
+            0 => {}
+            1 => {
+                waddch(win, ACS_VLINE());
+            }
+            _ => {
+                waddstr(win, " ");
+            }
+        }
+        waddstr(win, &column.cell(&column.header, width));
+        //Above the marker
+        if n == 0 {
+            waddstr(win, " ");
+        }
+    }
+    wattrset(win, A_NORMAL());
+    wrefresh(win);
+}
+
 fn get_pretty_time(seconds: f64) -> String {
     let hours = seconds as i64 / 3600;
     let mins = (seconds as i64 - hours * 3600) / 60;
     let secs = seconds as i64 % 60;
-    if seconds < 3600 as f64 {
+    if seconds < 3600f64 {
         format!(
             "{}:{}",
             if mins < 10 {
@@ -978,12 +1556,6 @@ fn get_pretty_time(seconds: f64) -> String {
     }
 }
 
-fn print_status(win: WINDOW, msg: &str) {
-    wmove(win, 1, 0);
-    wprintw(win, msg);
-    wrefresh(win);
-}
-
 fn jump_to_current(
     playlist: &Playlist,
     playlist_canvas: &PlaylistCanvas,
@@ -999,17 +1571,6 @@ fn jump_to_current(
     try_center_id(playlist, playlist_canvas, current_id)
 }
 
-fn search_playlist(playlist: &Playlist, search_pattern: &str) -> Vec<usize> {
-    let mut output: Vec<usize> = vec![];
-    for ref entry in playlist.0.iter() {
-        if entry.filename.contains(search_pattern) || entry.title.contains(search_pattern) {
-            output.push(entry.id);
-        }
-    }
-
-    output
-}
-
 fn try_center_id(
     playlist: &Playlist,
     canvas: &PlaylistCanvas,
@@ -1023,7 +1584,7 @@ fn try_center_id(
             top_line = 0;
             bottom_line = top_line + line_count;
         } else if id > playlist.0.len() - line_count / 2 {
-            bottom_line = playlist.0.len();
+            bottom_line = std::cmp::max(playlist.0.len(), line_count);
             top_line = bottom_line - line_count;
         } else {
             top_line = id - line_count / 2;
@@ -1034,7 +1595,10 @@ fn try_center_id(
             top_line,
             bottom_line,
             selected_line: id,
+            grabbed: canvas.grabbed,
+            marked: canvas.marked.clone(),
+            visual_start: canvas.visual_start,
         });
     }
-    return None;
+    None
 }
\ No newline at end of file
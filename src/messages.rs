@@ -0,0 +1,89 @@
+use libc;
+use ncurses::{attr_t, A_NORMAL};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use theme::{self, Element};
+
+/// Number of messages kept for the message history.
+const HISTORY_LENGTH: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn attributes(self) -> attr_t {
+        match self {
+            Severity::Info => A_NORMAL(),
+            Severity::Warning => theme::attributes(Element::Warning),
+            Severity::Error => theme::attributes(Element::Error),
+        }
+    }
+}
+
+pub struct Message {
+    pub text: String,
+    pub severity: Severity,
+    received: SystemTime,
+    expires: Instant,
+}
+
+impl Message {
+    /// The local time the message was received at as `hh:mm:ss`.
+    pub fn time(&self) -> String {
+        let seconds = self
+            .received
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0) as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::localtime_r(&seconds, &mut tm);
+        }
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+/// Status bar messages, shown until they expire and kept for the message history afterwards.
+#[derive(Default)]
+pub struct Messages {
+    history: VecDeque<Message>,
+}
+
+impl Messages {
+    pub fn push(&mut self, text: String, severity: Severity, timeout: Duration) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(Message {
+            text,
+            severity,
+            received: SystemTime::now(),
+            expires: Instant::now() + timeout,
+        });
+    }
+
+    /// The newest message which has not expired yet.
+    pub fn current(&self) -> Option<&Message> {
+        let now = Instant::now();
+        self.history.iter().rev().find(|message| message.expires > now)
+    }
+
+    /// When the status bar has to be redrawn because a message expires.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.history
+            .iter()
+            .map(|message| message.expires)
+            .filter(|&expires| expires > now)
+            .min()
+    }
+
+    /// All messages, the newest first.
+    pub fn history(&self) -> impl Iterator<Item = &Message> {
+        self.history.iter().rev()
+    }
+}
@@ -0,0 +1,123 @@
+use libc;
+use mpvipc::{Error, ErrorCode, Mpv};
+use serde_json::{self, Value};
+use std::cell::Cell;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local!(static GUARDED: Cell<bool> = const { Cell::new(false) });
+
+/// Commands which are missing in mpvipc or need their arguments escaped.
+pub trait MpvExt {
+    fn load_file(&self, path: &str, mode: &str) -> Result<(), Error>;
+    fn load_list(&self, path: &str, mode: &str) -> Result<(), Error>;
+    fn is_connected(&self) -> bool;
+    fn run_raw(&self, command: &Value) -> Result<Value, Error>;
+    fn get_json_property(&self, name: &str) -> Result<Value, Error>;
+}
+
+impl MpvExt for Mpv {
+    /// Runs `loadfile <path> <mode>` where mode is one of `replace`, `append` or `append-play`.
+    fn load_file(&self, path: &str, mode: &str) -> Result<(), Error> {
+        //mpvipc puts the arguments into the JSON command as they are
+        self.run_command("loadfile", &[&json_escape(path), mode])
+    }
+
+    /// Runs `loadlist <path> <mode>` where mode is `replace` or `append`.
+    fn load_list(&self, path: &str, mode: &str) -> Result<(), Error> {
+        self.run_command("loadlist", &[&json_escape(path), mode])
+    }
+
+    /// Checks whether mpv is still at the other end of the socket. mpvipc panics when writing
+    /// to a closed socket and waits forever for a reply that never comes.
+    fn is_connected(&self) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.get_stream_ref().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+        ready >= 0 && fd.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) == 0
+    }
+
+    /// Sends a command object like `{"command": ["get_property", "volume"]}` as it is and
+    /// returns the `data` of the reply.
+    fn run_raw(&self, command: &Value) -> Result<Value, Error> {
+        let connection_lost = |why: String| Error(ErrorCode::ConnectError(why));
+        let mut stream = self.get_stream_ref();
+        stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .map_err(|why| connection_lost(why.to_string()))?;
+
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => return Err(connection_lost(String::from("Connection to mpv lost"))),
+                Ok(_) => {}
+                Err(why) => return Err(connection_lost(why.to_string())),
+            }
+            //Events arrive on the same socket and have no error field
+            let mut reply: Value = match serde_json::from_str(&line) {
+                Ok(reply) => reply,
+                Err(_) => continue,
+            };
+            match reply.get("error") {
+                Some(Value::String(error)) if error == "success" => {}
+                Some(Value::String(error)) => {
+                    return Err(Error(ErrorCode::MpvError(error.clone())))
+                }
+                _ => continue,
+            }
+            return Ok(reply.get_mut("data").map_or(Value::Null, Value::take));
+        }
+    }
+
+    /// Reads a property as JSON. mpvipc panics on some values of nested properties like
+    /// `track-list`.
+    fn get_json_property(&self, name: &str) -> Result<Value, Error> {
+        let mut command = serde_json::Map::new();
+        command.insert(
+            String::from("command"),
+            Value::Array(vec![Value::from("get_property"), Value::from(name)]),
+        );
+        self.run_raw(&Value::Object(command))
+    }
+}
+
+pub fn json_escape(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Runs mpvipc calls which panic instead of returning an error when the socket breaks
+/// and turns such a panic into an error.
+pub fn guard<T, E, F>(f: F) -> Result<T, E>
+where
+    E: From<Error>,
+    F: FnOnce() -> Result<T, E>,
+{
+    let outer = GUARDED.with(|guarded| guarded.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    GUARDED.with(|guarded| guarded.set(outer));
+    result.unwrap_or_else(|_| {
+        Err(Error(ErrorCode::ConnectError(String::from("Connection to mpv lost"))).into())
+    })
+}
+
+/// Whether the current thread runs inside `guard()`, so a panic is expected.
+pub fn is_guarded() -> bool {
+    GUARDED.with(Cell::get)
+}
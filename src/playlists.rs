@@ -0,0 +1,88 @@
+use config;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: &[&str] = &["m3u", "m3u8", "pls"];
+
+/// An entry of a playlist file. Titles and durations are only written if they are known.
+pub struct Item {
+    pub path: String,
+    pub title: Option<String>,
+    pub duration: Option<f64>,
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            EXTENSIONS.contains(&ext.as_str())
+        }
+        None => false,
+    }
+}
+
+/// Where the playlist called `name` is saved. A bare name goes into the playlist directory and
+/// gets the `.m3u8` extension unless it has one already, paths are used as they are.
+pub fn path_of(name: &str) -> PathBuf {
+    if name.contains('/') {
+        return config::expand_tilde(name);
+    }
+    let path = config::get().playlist_dir.join(name);
+    if is_playlist(&path) {
+        path
+    } else {
+        path.with_file_name(format!("{}.m3u8", name))
+    }
+}
+
+/// Writes `items` as PLS if the file name ends with `.pls`, as extended M3U otherwise.
+pub fn save(path: &Path, items: &[Item]) -> io::Result<()> {
+    let is_pls = path
+        .extension()
+        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("pls"));
+    let content = if is_pls { to_pls(items) } else { to_m3u(items) };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content)
+}
+
+fn to_m3u(items: &[Item]) -> String {
+    let mut output = String::from("#EXTM3U\n");
+    for item in items {
+        if item.title.is_some() || item.duration.is_some() {
+            output.push_str(&format!(
+                "#EXTINF:{},{}\n",
+                seconds(item.duration),
+                item.title.as_ref().map_or("", |title| one_line(title))
+            ));
+        }
+        output.push_str(&item.path);
+        output.push('\n');
+    }
+    output
+}
+
+fn to_pls(items: &[Item]) -> String {
+    let mut output = String::from("[playlist]\n");
+    for (i, item) in items.iter().enumerate() {
+        output.push_str(&format!("File{}={}\n", i + 1, item.path));
+        if let Some(ref title) = item.title {
+            output.push_str(&format!("Title{}={}\n", i + 1, one_line(title)));
+        }
+        output.push_str(&format!("Length{}={}\n", i + 1, seconds(item.duration)));
+    }
+    output.push_str(&format!("NumberOfEntries={}\nVersion=2\n", items.len()));
+    output
+}
+
+/// Whole seconds, -1 stands for an unknown duration in both formats.
+fn seconds(duration: Option<f64>) -> i64 {
+    duration.map_or(-1, |duration| duration.round() as i64)
+}
+
+//A line break would end the entry early
+fn one_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
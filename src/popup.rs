@@ -0,0 +1,152 @@
+use ncurses::*;
+use text::fit;
+use theme::{self, Element};
+
+/// What choosing a line of a popup does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pick {
+    //Seeks to the chapter with the index of the line
+    Chapter,
+    //Selects the track with the index of the line in `track-list`
+    Track,
+}
+
+/// A scrollable list drawn in a box on top of the playlist or the file browser.
+pub struct Popup {
+    pub title: String,
+    pub lines: Vec<(String, attr_t)>,
+    pub top_line: usize,
+    //Only set if a line can be chosen, with the selected line
+    pub pick: Option<(Pick, usize)>,
+}
+
+impl Popup {
+    pub fn new(title: &str, lines: Vec<(String, attr_t)>) -> Popup {
+        Popup {
+            title: String::from(title),
+            lines,
+            top_line: 0,
+            pick: None,
+        }
+    }
+
+    /// A popup to choose one of `lines` from, starting at `selected`.
+    pub fn picker(
+        title: &str,
+        lines: Vec<(String, attr_t)>,
+        pick: Pick,
+        selected: usize,
+        height: i32,
+    ) -> Popup {
+        let mut popup = Popup::new(title, lines);
+        popup.pick = Some((pick, 0));
+        popup.select(selected as isize, height);
+        popup
+    }
+
+    /// Moves the selection of a picker by `offset` lines, other popups are scrolled.
+    pub fn move_selection(&mut self, offset: isize, height: i32) {
+        match self.pick {
+            Some((_, selected)) => self.select(selected as isize + offset, height),
+            None => self.scroll(offset, height),
+        }
+    }
+
+    fn select(&mut self, line: isize, height: i32) {
+        let selected = if line < 0 {
+            0
+        } else {
+            std::cmp::min(line as usize, self.lines.len().saturating_sub(1))
+        };
+        if let Some((_, ref mut current)) = self.pick {
+            *current = selected;
+        }
+        let visible = visible_lines(height);
+        if selected < self.top_line {
+            self.top_line = selected;
+        } else if selected >= self.top_line + visible {
+            self.top_line = selected + 1 - visible;
+        }
+    }
+
+    /// Scrolls by `offset` lines, `height` being the height of the window below the popup.
+    pub fn scroll(&mut self, offset: isize, height: i32) {
+        let visible = visible_lines(height);
+        let max_top = self.lines.len().saturating_sub(visible) as isize;
+        let top_line = self.top_line as isize + offset;
+        self.top_line = if top_line < 0 {
+            0
+        } else if top_line > max_top {
+            max_top as usize
+        } else {
+            top_line as usize
+        };
+    }
+}
+
+/// Draws the popup centered over the area of `parent`.
+pub fn print_popup(parent: WINDOW, popup: &Popup) {
+    let mut height = 0;
+    let mut width = 0;
+    let mut y = 0;
+    let mut x = 0;
+    getmaxyx(parent, &mut height, &mut width);
+    getbegyx(parent, &mut y, &mut x);
+
+    let popup_width = std::cmp::max(width - 8, std::cmp::min(width, 20));
+    let popup_height = std::cmp::min(height, popup.lines.len() as i32 + 2);
+    let win = newwin(
+        std::cmp::max(popup_height, 3),
+        popup_width,
+        y + (height - popup_height) / 2,
+        x + (width - popup_width) / 2,
+    );
+    if win.is_null() {
+        return;
+    }
+
+    box_(win, 0, 0);
+    let title = format!(" {} ", popup.title);
+    wmove(win, 0, 2);
+    wattron(win, A_BOLD());
+    waddstr(win, &fit(&title, (popup_width as usize).saturating_sub(4)));
+    wattroff(win, A_BOLD());
+
+    let inner_width = (popup_width as usize).saturating_sub(2);
+    let visible = visible_lines(height);
+    for (row, &(ref line, attributes)) in popup
+        .lines
+        .iter()
+        .skip(popup.top_line)
+        .take(visible)
+        .enumerate()
+    {
+        let attributes = match popup.pick {
+            Some((_, selected)) if selected == popup.top_line + row => {
+                theme::merge(attributes, theme::attributes(Element::Selected))
+            }
+            _ => attributes,
+        };
+        wmove(win, row as i32 + 1, 1);
+        wattrset(win, attributes);
+        waddstr(win, &fit(line, inner_width));
+        wattrset(win, A_NORMAL());
+    }
+
+    //Show that there is more to scroll
+    if popup.top_line + visible < popup.lines.len() {
+        wmove(win, popup_height - 1, popup_width - 5);
+        waddstr(win, " v ");
+    }
+    if popup.top_line > 0 {
+        wmove(win, 0, popup_width - 5);
+        waddstr(win, " ^ ");
+    }
+    wrefresh(win);
+    delwin(win);
+}
+
+/// Number of lines of the popup shown over a window of `height` lines.
+fn visible_lines(height: i32) -> usize {
+    std::cmp::max(height - 2, 1) as usize
+}
@@ -0,0 +1,436 @@
+use columns::Field;
+use mpvipc::ipc::PlaylistEntry;
+use mpvipc::Playlist;
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+use tags::{Info, Tags};
+
+/// How the terms of a search are matched.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    //The text occurs as it is
+    Plain,
+    Regex,
+    //The characters occur in this order, the closer together the better
+    Fuzzy,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "plain" => Some(Mode::Plain),
+            "regex" => Some(Mode::Regex),
+            "fuzzy" => Some(Mode::Fuzzy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Plain => "plain",
+            Mode::Regex => "regex",
+            Mode::Fuzzy => "fuzzy",
+        }
+    }
+
+    pub fn next(self) -> Mode {
+        match self {
+            Mode::Plain => Mode::Regex,
+            Mode::Regex => Mode::Fuzzy,
+            Mode::Fuzzy => Mode::Plain,
+        }
+    }
+}
+
+enum Matcher {
+    Plain { pattern: Vec<char>, ignore_case: bool },
+    Regex(Regex),
+    Fuzzy { pattern: Vec<char>, ignore_case: bool },
+}
+
+impl Matcher {
+    /// With `smart_case` the case only matters if the pattern has an upper case letter.
+    fn new(pattern: &str, mode: Mode, smart_case: bool) -> Result<Matcher, String> {
+        let ignore_case = smart_case && !pattern.chars().any(char::is_uppercase);
+        let chars = pattern.chars().map(|c| fold(c, ignore_case)).collect();
+        match mode {
+            Mode::Plain => Ok(Matcher::Plain { pattern: chars, ignore_case }),
+            Mode::Regex => RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map(Matcher::Regex)
+                .map_err(|_| String::from("invalid regex")),
+            Mode::Fuzzy => Ok(Matcher::Fuzzy { pattern: chars, ignore_case }),
+        }
+    }
+
+    /// The score of `text`, lower is better, and the byte ranges which matched.
+    fn find(&self, text: &str) -> Option<(usize, Vec<Range<usize>>)> {
+        match *self {
+            Matcher::Plain { ref pattern, ignore_case } => {
+                let ranges = occurrences(text, pattern, ignore_case);
+                if ranges.is_empty() {
+                    None
+                } else {
+                    Some((0, ranges))
+                }
+            }
+            Matcher::Regex(ref regex) => {
+                if !regex.is_match(text) {
+                    return None;
+                }
+                let ranges = regex
+                    .find_iter(text)
+                    .map(|found| found.range())
+                    .filter(|range| !range.is_empty())
+                    .collect();
+                Some((0, ranges))
+            }
+            Matcher::Fuzzy { ref pattern, ignore_case } => subsequence(text, pattern, ignore_case),
+        }
+    }
+}
+
+fn fold(c: char, ignore_case: bool) -> char {
+    if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+/// The places where `pattern` occurs in `text`, without overlapping.
+fn occurrences(text: &str, pattern: &[char], ignore_case: bool) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i + pattern.len() <= chars.len() {
+        let matches = chars[i..i + pattern.len()]
+            .iter()
+            .zip(pattern)
+            .all(|(&(_, c), &p)| fold(c, ignore_case) == p);
+        if matches {
+            let end = chars.get(i + pattern.len()).map_or(text.len(), |&(end, _)| end);
+            ranges.push(chars[i].0..end);
+            i += pattern.len();
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+/// Finds the characters of `pattern` in `text` in the same order. Every start is tried and the
+/// one spreading them the least wins, starting at a word counts a bit more.
+fn subsequence(text: &str, pattern: &[char], ignore_case: bool) -> Option<(usize, Vec<Range<usize>>)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let first = match pattern.first() {
+        Some(&first) => first,
+        None => return Some((0, vec![])),
+    };
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for start in 0..chars.len() {
+        if fold(chars[start].1, ignore_case) != first {
+            continue;
+        }
+        let mut positions = vec![start];
+        for &p in &pattern[1..] {
+            let from = positions[positions.len() - 1] + 1;
+            match (from..chars.len()).find(|&i| fold(chars[i].1, ignore_case) == p) {
+                Some(i) => positions.push(i),
+                None => break,
+            }
+        }
+        if positions.len() < pattern.len() {
+            //A later start can't find more of them
+            break;
+        }
+        let gaps = positions[positions.len() - 1] + 1 - start - pattern.len();
+        let inside_word = start > 0 && chars[start - 1].1.is_alphanumeric();
+        let score = gaps * 2 + inside_word as usize;
+        if best.as_ref().is_none_or(|&(best, _)| score < best) {
+            best = Some((score, positions));
+        }
+    }
+
+    best.map(|(score, positions)| {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for i in positions {
+            let (start, c) = chars[i];
+            let end = start + c.len_utf8();
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        (score, ranges)
+    })
+}
+
+struct Term {
+    //Any of them has to match
+    fields: Vec<Field>,
+    matcher: Matcher,
+}
+
+/// A parsed search. Text before the first `field:` looks at the title, artist, album and path,
+/// `field:value` only at the field, which can be any tag. All of them have to match.
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(text: &str, mode: Mode, smart_case: bool) -> Result<Query, String> {
+        let mut terms = vec![];
+        let mut fields = free_text_fields(mode);
+        let mut start = 0;
+        for (position, token) in tokens(text) {
+            if let Some(name) = qualifier(token) {
+                add_term(&mut terms, fields, &text[start..position], mode, smart_case)?;
+                fields = vec![Field::from_name(&name.to_lowercase())];
+                start = position + name.len() + 1;
+            }
+        }
+        add_term(&mut terms, fields, &text[start..], mode, smart_case)?;
+        Ok(Query { terms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether files have to be read for a tag the search asks for explicitly.
+    fn needs_tags(&self) -> bool {
+        self.terms.iter().any(|term| term.fields.len() == 1 && term.fields[0].needs_tags())
+    }
+
+    /// The score of the entry if all terms match, lower is better.
+    fn score(&self, entry: &PlaylistEntry, info: Option<&Info>) -> Option<usize> {
+        let mut total = 0;
+        for term in &self.terms {
+            total += term
+                .fields
+                .iter()
+                .filter_map(|field| term.matcher.find(&field.text(entry, info)))
+                .map(|(score, _)| score)
+                .min()?;
+        }
+        Some(total)
+    }
+
+    /// The byte ranges of `text`, shown in a column of `field`, which the search matched.
+    pub fn highlights(&self, field: &Field, text: &str) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = self
+            .terms
+            .iter()
+            .filter(|term| term.fields.contains(field))
+            .filter_map(|term| term.matcher.find(text))
+            .flat_map(|(_, ranges)| ranges)
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = std::cmp::max(last.end, range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+fn free_text_fields(mode: Mode) -> Vec<Field> {
+    let mut fields = vec![
+        Field::Title,
+        Field::Tag(String::from("artist")),
+        Field::Tag(String::from("album")),
+        Field::Filename,
+    ];
+    //The characters of a short pattern are found somewhere in almost any directory
+    if mode != Mode::Fuzzy {
+        fields.push(Field::Path);
+    }
+    fields
+}
+
+//Empty terms are left out, they would match everything while `artist:` is being typed
+fn add_term(
+    terms: &mut Vec<Term>,
+    fields: Vec<Field>,
+    pattern: &str,
+    mode: Mode,
+    smart_case: bool,
+) -> Result<(), String> {
+    let pattern = pattern.trim();
+    if !pattern.is_empty() {
+        terms.push(Term {
+            fields,
+            matcher: Matcher::new(pattern, mode, smart_case)?,
+        });
+    }
+    Ok(())
+}
+
+/// The words of `text` separated by whitespace and where they start.
+fn tokens(text: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(begin)) => {
+                tokens.push((begin, &text[begin..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        tokens.push((begin, &text[begin..]));
+    }
+    tokens
+}
+
+/// The field name of a `field:value` word. Tags followed by `/` are taken for the scheme of a
+/// URL like `https://`.
+fn qualifier(token: &str) -> Option<&str> {
+    let colon = token.find(':')?;
+    let name = &token[..colon];
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        return None;
+    }
+    match Field::from_name(&name.to_lowercase()) {
+        Field::Tag(_) if token[colon + 1..].starts_with('/') => None,
+        _ => Some(name),
+    }
+}
+
+/// The ids of the entries matching `query`, the best first. Entries which match equally well
+/// stay in the order of the playlist, so only fuzzy searches change it.
+pub fn search(playlist: &Playlist, tags: &mut Tags, query: &Query) -> Vec<usize> {
+    if query.is_empty() {
+        return vec![];
+    }
+    let read_tags = query.needs_tags();
+    let mut results = vec![];
+    for entry in &playlist.0 {
+        let info = if read_tags {
+            tags.get(&entry.filename)
+        } else {
+            tags.peek(&entry.filename)
+        };
+        if let Some(score) = query.score(entry, info) {
+            results.push((score, entry.id));
+        }
+    }
+    ranked(results)
+}
+
+//The sort is stable, so equal scores keep the order of the playlist
+fn ranked(mut results: Vec<(usize, usize)>) -> Vec<usize> {
+    results.sort_by_key(|&(score, _)| score);
+    results.into_iter().map(|(_, id)| id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(filename: &str, title: &str) -> PlaylistEntry {
+        PlaylistEntry {
+            id: 0,
+            filename: filename.to_string(),
+            title: title.to_string(),
+            current: false,
+        }
+    }
+
+    fn matches(query: &str, entry: &PlaylistEntry) -> bool {
+        score(query, Mode::Plain, true, entry).is_some()
+    }
+
+    fn score(query: &str, mode: Mode, smart_case: bool, entry: &PlaylistEntry) -> Option<usize> {
+        let query = Query::parse(query, mode, smart_case).unwrap();
+        query.score(entry, None)
+    }
+
+    fn fuzzy(pattern: &str, text: &str) -> Option<usize> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        subsequence(text, &pattern, true).map(|(score, _)| score)
+    }
+
+    #[test]
+    fn urls_are_no_field_queries() {
+        let stream = entry("https://youtube.com/watch?v=1", "");
+        assert!(matches("https://youtube", &stream));
+        assert!(matches("title:https://youtube", &stream));
+        assert!(!matches("https://YouTube", &stream));
+        assert_eq!(qualifier("https://youtube"), None);
+        assert_eq!(qualifier("genre:rock"), Some("genre"));
+        assert_eq!(qualifier("artist:"), Some("artist"));
+    }
+
+    #[test]
+    fn built_in_fields_may_start_with_a_slash() {
+        let song = entry("/music/heroes.flac", "Heroes");
+        assert!(matches("path:/music", &song));
+        assert!(matches("title:heroes path:/music", &song));
+        assert!(!matches("title:/music", &song));
+        assert!(!matches("artist:heroes", &song));
+    }
+
+    #[test]
+    fn smart_case() {
+        let song = entry("01", "Heroes");
+        assert!(matches("heroes", &song));
+        assert!(matches("Heroes", &song));
+        assert!(!matches("HEROES", &song));
+        assert!(score("heroes", Mode::Plain, false, &song).is_none());
+        assert!(score("lom", Mode::Fuzzy, true, &entry("01", "Life on Mars")).is_some());
+        assert!(score("LOM", Mode::Fuzzy, true, &entry("01", "Life on Mars")).is_none());
+    }
+
+    #[test]
+    fn regex_mode() {
+        let song = entry("01", "Heroes");
+        assert!(score("^her.*s$", Mode::Regex, true, &song).is_some());
+        assert!(score("^HER", Mode::Regex, true, &song).is_none());
+        assert!(score("title:o(es|ne)$", Mode::Regex, true, &song).is_some());
+        assert_eq!(
+            Query::parse("(", Mode::Regex, true).err(),
+            Some(String::from("invalid regex"))
+        );
+        //Only regex mode gives special characters a meaning
+        assert!(Query::parse("(", Mode::Plain, true).is_ok());
+        assert!(!matches("(", &song));
+    }
+
+    #[test]
+    fn fuzzy_prefers_close_characters_and_word_starts() {
+        assert_eq!(fuzzy("lom", "Lomax"), Some(0));
+        assert_eq!(fuzzy("lom", "Life on Mars"), Some(12));
+        assert_eq!(fuzzy("ars", "Ars Nova"), Some(0));
+        assert_eq!(fuzzy("ars", "Mars"), Some(1));
+        assert_eq!(fuzzy("xyz", "Mars"), None);
+        //The closest of several places counts
+        assert_eq!(fuzzy("ash", "Ashes to Ashes"), Some(0));
+        assert_eq!(fuzzy("sa", "Ashes to Ashes"), Some(9));
+    }
+
+    #[test]
+    fn fuzzy_results_are_ranked_and_ties_keep_their_order() {
+        let titles = ["Ashes to Ashes", "Starman", "Heroes", "Starman"];
+        let query = Query::parse("sa", Mode::Fuzzy, true).unwrap();
+        let results = titles
+            .iter()
+            .enumerate()
+            .filter_map(|(id, title)| {
+                let mut song = entry("01", title);
+                song.id = id;
+                query.score(&song, None).map(|score| (score, id))
+            })
+            .collect();
+        assert_eq!(ranked(results), vec![1, 3, 0]);
+    }
+}
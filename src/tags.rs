@@ -0,0 +1,169 @@
+use messages::Severity;
+use mpvipc::MpvDataType;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use Repaint;
+
+/// What is known about a file of the playlist.
+#[derive(Default)]
+pub struct Info {
+    //Lower case names
+    tags: HashMap<String, String>,
+    pub duration: Option<f64>,
+}
+
+impl Info {
+    /// Looks up a tag ignoring its case, empty ones count as missing.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .get(&name.to_lowercase())
+            .map(String::as_str)
+            .filter(|value| !value.trim().is_empty())
+    }
+}
+
+/// The tags and durations of the playlist entries by file name. mpv only knows them for the
+/// current file, the others are read with `ffprobe` in the background when they are shown.
+pub struct Tags {
+    infos: HashMap<String, Info>,
+    //The files which have been read or are waiting for it
+    requested: HashSet<String>,
+    requests: mpsc::Sender<String>,
+    //Where mpv was started, relative paths are relative to it
+    working_directory: Option<String>,
+}
+
+impl Tags {
+    pub fn new(working_directory: Option<String>, tx: mpsc::Sender<Repaint>) -> Arc<Mutex<Tags>> {
+        let (requests, rx) = mpsc::channel();
+        let tags = Arc::new(Mutex::new(Tags {
+            infos: HashMap::new(),
+            requested: HashSet::new(),
+            requests,
+            working_directory,
+        }));
+        let cache = tags.clone();
+        thread::Builder::new()
+            .name("tag_reader".into())
+            .spawn(move || read_requested(&cache, &rx, &tx))
+            .unwrap();
+        tags
+    }
+
+    /// What is known about `filename`, reading it is started if it hasn't been yet.
+    pub fn get(&mut self, filename: &str) -> Option<&Info> {
+        //Streams would be downloaded
+        if !filename.contains("://") && self.requested.insert(filename.to_string()) {
+            let _ = self.requests.send(filename.to_string());
+        }
+        self.infos.get(filename)
+    }
+
+    /// What is known about `filename` without reading it.
+    pub fn peek(&self, filename: &str) -> Option<&Info> {
+        self.infos.get(filename)
+    }
+
+    /// Takes the metadata mpv reports for the current file.
+    pub fn set_metadata(&mut self, filename: &str, metadata: &HashMap<String, MpvDataType>) {
+        let info = self.info_mut(filename);
+        for (name, value) in metadata {
+            if let MpvDataType::String(ref value) = *value {
+                info.tags.insert(name.to_lowercase(), value.clone());
+            }
+        }
+    }
+
+    /// Set on every connect, mpv may have been restarted somewhere else.
+    pub fn set_working_directory(&mut self, working_directory: Option<String>) {
+        self.working_directory = working_directory;
+    }
+
+    pub fn set_duration(&mut self, filename: &str, duration: f64) {
+        self.info_mut(filename).duration = Some(duration);
+    }
+
+    fn info_mut(&mut self, filename: &str) -> &mut Info {
+        self.infos.entry(filename.to_string()).or_default()
+    }
+}
+
+/// Reads the requested files one after another and repaints the playlist for each of them.
+fn read_requested(tags: &Mutex<Tags>, rx: &mpsc::Receiver<String>, tx: &mpsc::Sender<Repaint>) {
+    for filename in rx {
+        let working_directory = tags.lock().unwrap().working_directory.clone();
+        let path = match working_directory {
+            Some(ref dir) if Path::new(&filename).is_relative() => {
+                Path::new(dir).join(&filename).to_string_lossy().into_owned()
+            }
+            _ => filename.clone(),
+        };
+        let info = match probe(&path) {
+            Ok(info) => info,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+                tx.send(Repaint::Message(
+                    String::from("ffprobe not found, only the current song has tags"),
+                    Severity::Warning,
+                )).unwrap();
+                return;
+            }
+            Err(_) => Info::default(),
+        };
+
+        {
+            //mpv may have reported the file in the meantime
+            let mut tags = tags.lock().unwrap();
+            let known = tags.info_mut(&filename);
+            for (name, value) in info.tags {
+                known.tags.entry(name).or_insert(value);
+            }
+            if known.duration.is_none() {
+                known.duration = info.duration;
+            }
+        }
+        tx.send(Repaint::Playlist {
+            clear_win: false,
+            scroll_to_beginning: false,
+        }).unwrap();
+    }
+}
+
+/// Reads the tags of the container and its streams and the duration of a file.
+fn probe(path: &str) -> io::Result<Info> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_entries",
+            "format=duration:format_tags:stream_tags",
+            "-i",
+            path,
+        ])
+        .output()?;
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap_or(Value::Null);
+
+    let mut info = Info::default();
+    let streams = json["streams"].as_array().cloned().unwrap_or_default();
+    //Ogg files keep their tags in the stream, the container tags take precedence
+    for tags in streams.iter().map(|stream| &stream["tags"]).chain(Some(&json["format"]["tags"])) {
+        if let Some(tags) = tags.as_object() {
+            for (name, value) in tags {
+                if let Some(value) = value.as_str() {
+                    info.tags.insert(name.to_lowercase(), value.to_string());
+                }
+            }
+        }
+    }
+    info.duration = json["format"]["duration"]
+        .as_str()
+        .and_then(|duration| duration.parse().ok());
+    Ok(info)
+}
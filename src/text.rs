@@ -0,0 +1,176 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const ELLIPSIS: &str = "...";
+
+/// The number of terminal cells `text` takes. Wide characters like CJK and most emoji take two,
+/// combining characters none.
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+//Control characters would move the cursor, they are shown as a space
+fn grapheme_width(grapheme: &str) -> usize {
+    if grapheme.chars().any(char::is_control) {
+        1
+    } else {
+        grapheme.width()
+    }
+}
+
+/// Shortens `text` to at most `max_width` cells, ending it with `...` if something was cut off.
+/// Characters are never split, even if they are made up of several code points.
+pub fn truncate(text: &str, max_width: usize) -> String {
+    if width(text) <= max_width {
+        return printable(text);
+    }
+    //Too narrow for the ellipsis to leave room for any text
+    let ellipsis = if max_width > ELLIPSIS.len() { ELLIPSIS } else { "" };
+    let room = max_width - ellipsis.len();
+
+    let mut output = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        let width = grapheme_width(grapheme);
+        if used + width > room {
+            break;
+        }
+        used += width;
+        output.push_str(&printable(grapheme));
+    }
+    output.push_str(ellipsis);
+    output
+}
+
+/// Truncates or pads `text` on the right to exactly `width` cells.
+pub fn fit(text: &str, width: usize) -> String {
+    let output = truncate(text, width);
+    let padding = width.saturating_sub(self::width(&output));
+    output + &" ".repeat(padding)
+}
+
+/// Truncates or pads `text` on the left to exactly `width` cells.
+pub fn fit_right(text: &str, width: usize) -> String {
+    let output = truncate(text, width);
+    " ".repeat(width.saturating_sub(self::width(&output))) + &output
+}
+
+/// The part of `text` shown in `width` cells after it has scrolled `offset` cells to the left.
+/// It comes in again from the right after a gap, so the offset can keep growing.
+pub fn scroll(text: &str, offset: usize, width: usize) -> String {
+    const GAP: &str = "   ";
+    let offset = offset % (self::width(text) + GAP.len());
+    let looped = format!("{}{}{}", text, GAP, text);
+
+    let mut output = String::new();
+    let mut skipped = 0;
+    let mut used = 0;
+    for grapheme in looped.graphemes(true) {
+        let grapheme_width = grapheme_width(grapheme);
+        if skipped < offset {
+            skipped += grapheme_width;
+            //Only the right half of a wide character is left
+            if skipped > offset && used < width {
+                output.push(' ');
+                used += 1;
+            }
+            continue;
+        }
+        if used + grapheme_width > width {
+            break;
+        }
+        used += grapheme_width;
+        output.push_str(&printable(grapheme));
+    }
+    output + &" ".repeat(width - used)
+}
+
+//A control character can be made up of several code points like `\r\n`, it's still one space
+fn printable(text: &str) -> String {
+    text.graphemes(true)
+        .map(|grapheme| if grapheme.chars().any(char::is_control) { " " } else { grapheme })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii() {
+        assert_eq!(width("Heroes"), 6);
+        assert_eq!(truncate("Heroes", 6), "Heroes");
+        assert_eq!(truncate("Life on Mars", 8), "Life ...");
+        assert_eq!(fit("Heroes", 8), "Heroes  ");
+        assert_eq!(fit_right("3:20", 6), "  3:20");
+    }
+
+    #[test]
+    fn japanese_takes_two_cells_per_character() {
+        let text = "東京事変";
+        assert_eq!(width(text), 8);
+        assert_eq!(truncate(text, 8), text);
+        assert_eq!(truncate(text, 7), "東京...");
+        //Half a character doesn't fit, the rest is padded
+        assert_eq!(fit(text, 6), "東... ");
+        assert_eq!(width(&fit(text, 6)), 6);
+        assert_eq!(fit_right("東京", 5), " 東京");
+    }
+
+    #[test]
+    fn emoji() {
+        assert_eq!(width("☕"), 2);
+        assert_eq!(width("Café ☕ Song"), 12);
+        assert_eq!(truncate("☕☕☕☕", 6), "☕...");
+        assert_eq!(width(&fit("a☕b", 3)), 3);
+        //A flag is made up of two code points but is one character
+        assert_eq!(width("🇨🇭🇨🇭🇨🇭"), 6);
+        assert_eq!(truncate("🇨🇭🇨🇭🇨🇭", 5), "🇨🇭...");
+    }
+
+    #[test]
+    fn combining_characters_stay_with_their_base() {
+        //e followed by a combining acute accent
+        let text = "Cafe\u{301} Cafe\u{301}";
+        assert_eq!(width(text), 9);
+        assert_eq!(truncate(text, 7), "Cafe\u{301}...");
+        assert_eq!(fit("e\u{301}", 3), "e\u{301}  ");
+    }
+
+    #[test]
+    fn narrow_widths() {
+        assert_eq!(truncate("Heroes", 3), "Her");
+        assert_eq!(truncate("Heroes", 0), "");
+        assert_eq!(fit("東京", 1), " ");
+        assert_eq!(fit("", 2), "  ");
+    }
+
+    #[test]
+    fn scrolling_wraps_around() {
+        assert_eq!(scroll("Heroes", 0, 4), "Hero");
+        assert_eq!(scroll("Heroes", 4, 4), "es  ");
+        assert_eq!(scroll("Heroes", 7, 4), "  He");
+        assert_eq!(scroll("Heroes", 9, 4), "Hero");
+        assert_eq!(scroll("Heroes", 2, 10), "roes   Her");
+    }
+
+    #[test]
+    fn scrolling_splits_no_characters() {
+        assert_eq!(scroll("東京事変", 1, 4), " 京 ");
+        assert_eq!(scroll("東京事変", 2, 4), "京事");
+        assert_eq!(scroll("Cafe\u{301}s", 3, 3), "e\u{301}s ");
+        for offset in 0..20 {
+            assert_eq!(width(&scroll("東京 ☕ Café", offset, 7)), 7);
+        }
+    }
+
+    #[test]
+    fn control_characters_are_replaced() {
+        assert_eq!(width("a\nb"), 3);
+        assert_eq!(fit("a\tb", 4), "a b ");
+        assert_eq!(width("a\r\nb"), 3);
+        assert_eq!(fit("a\r\nb", 3), "a b");
+        assert_eq!(fit_right("a\r\nb", 4), " a b");
+        assert_eq!(scroll("a\r\nb", 0, 3), "a b");
+    }
+}
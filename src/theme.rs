@@ -0,0 +1,247 @@
+use ncurses::*;
+use std::sync::OnceLock;
+
+static ATTRIBUTES: OnceLock<Vec<attr_t>> = OnceLock::new();
+
+/// The parts of the user interface which can be styled.
+#[derive(Clone, Copy)]
+pub enum Element {
+    Selected,
+    Current,
+    Marker,
+    Marked,
+    Grabbed,
+    Match,
+    ProgressFilled,
+    ProgressEmpty,
+    Label,
+    Error,
+    Warning,
+}
+
+/// Every element with its name in a theme, in the order of `Element`.
+const ELEMENTS: &[(Element, &str)] = &[
+    (Element::Selected, "selected"),
+    (Element::Current, "current"),
+    (Element::Marker, "marker"),
+    (Element::Marked, "marked"),
+    (Element::Grabbed, "grabbed"),
+    (Element::Match, "match"),
+    (Element::ProgressFilled, "progress_filled"),
+    (Element::ProgressEmpty, "progress_empty"),
+    (Element::Label, "label"),
+    (Element::Error, "error"),
+    (Element::Warning, "warning"),
+];
+
+/// The built-in themes with the style of every element. `default` only uses attributes
+/// and is what monochrome terminals fall back to.
+const THEMES: &[(&str, [&str; 11])] = &[
+    (
+        "default",
+        [
+            "reverse",
+            "bold",
+            "bold",
+            "underline",
+            "bold underline",
+            "bold reverse",
+            "",
+            "",
+            "",
+            "bold reverse",
+            "bold",
+        ],
+    ),
+    (
+        "dark",
+        [
+            "black on cyan",
+            "bold yellow",
+            "bold green",
+            "black on yellow",
+            "bold underline magenta",
+            "black on green",
+            "cyan",
+            "blue",
+            "bold cyan",
+            "bold white on red",
+            "bold yellow",
+        ],
+    ),
+    (
+        "light",
+        [
+            "white on blue",
+            "bold blue",
+            "bold red",
+            "black on cyan",
+            "bold underline green",
+            "black on yellow",
+            "blue",
+            "default",
+            "bold magenta",
+            "bold white on red",
+            "bold red",
+        ],
+    ),
+];
+
+const COLOR_NAMES: &[(&str, i16)] = &[
+    ("black", COLOR_BLACK),
+    ("red", COLOR_RED),
+    ("green", COLOR_GREEN),
+    ("yellow", COLOR_YELLOW),
+    ("blue", COLOR_BLUE),
+    ("magenta", COLOR_MAGENTA),
+    ("cyan", COLOR_CYAN),
+    ("white", COLOR_WHITE),
+];
+
+#[derive(Clone, Copy)]
+struct Style {
+    //-1 is the default color of the terminal
+    fg: i16,
+    bg: i16,
+    attributes: attr_t,
+}
+
+impl Style {
+    /// Parses styles like `bold white on red`, `reverse` or `bright-black on 236`.
+    fn parse(spec: &str) -> Result<Style, String> {
+        let mut style = Style {
+            fg: -1,
+            bg: -1,
+            attributes: A_NORMAL(),
+        };
+        let mut words = spec.split_whitespace();
+        while let Some(word) = words.next() {
+            let word = word.to_lowercase();
+            if word == "on" {
+                style.bg = match words.next().map(|color| parse_color(&color.to_lowercase())) {
+                    Some(Some(color)) => color,
+                    _ => return Err(format!("expected a color after `on` in `{}`", spec)),
+                };
+            } else if let Some(color) = parse_color(&word) {
+                style.fg = color;
+            } else {
+                style.attributes |= match word.as_str() {
+                    "bold" => A_BOLD(),
+                    "dim" => A_DIM(),
+                    "italic" => A_ITALIC(),
+                    "underline" => A_UNDERLINE(),
+                    "reverse" => A_REVERSE(),
+                    "blink" => A_BLINK(),
+                    "standout" => A_STANDOUT(),
+                    "normal" => A_NORMAL(),
+                    _ => return Err(format!("unknown color or attribute `{}`", word)),
+                };
+            }
+        }
+        Ok(style)
+    }
+
+    fn has_colors(&self) -> bool {
+        self.fg >= 0 || self.bg >= 0
+    }
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    styles: Vec<Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::builtin("default").unwrap_or_else(|| unreachable!("missing default theme"))
+    }
+}
+
+impl Theme {
+    pub fn builtin(name: &str) -> Option<Theme> {
+        let (_, specs) = THEMES.iter().find(|&&(n, _)| n == name)?;
+        let styles = specs
+            .iter()
+            .map(|spec| Style::parse(spec).unwrap_or_else(|_| unreachable!("invalid built-in style")))
+            .collect();
+        Some(Theme { styles })
+    }
+
+    /// Changes the style of the element called `name`.
+    pub fn set(&mut self, name: &str, spec: &str) -> Result<(), String> {
+        let index = match ELEMENTS.iter().position(|&(_, n)| n == name) {
+            Some(index) => index,
+            None => return Err(format!("unknown theme element `{}`", name)),
+        };
+        self.styles[index] = Style::parse(spec)?;
+        Ok(())
+    }
+}
+
+/// Sets up the color pairs of `theme`. Elements which need colors the terminal doesn't have
+/// get the attributes of the default theme instead.
+pub fn init(theme: &Theme) {
+    let has_colors = has_colors() && start_color() != ERR;
+    let default_colors = has_colors && use_default_colors() != ERR;
+    let fallback = Theme::default();
+
+    let attributes = theme
+        .styles
+        .iter()
+        .zip(&fallback.styles)
+        .enumerate()
+        .map(|(i, (style, fallback))| {
+            if !style.has_colors() {
+                return style.attributes;
+            }
+            //Without default colors the terminal is assumed to be white on black
+            let fg = if style.fg < 0 && !default_colors { COLOR_WHITE } else { style.fg };
+            let bg = if style.bg < 0 && !default_colors { COLOR_BLACK } else { style.bg };
+            let pair = i as i16 + 1;
+            if has_colors && fg < COLORS() as i16 && bg < COLORS() as i16 &&
+                init_pair(pair, fg, bg) != ERR
+            {
+                style.attributes | COLOR_PAIR(pair)
+            } else {
+                style.attributes | fallback.attributes
+            }
+        })
+        .collect();
+    let _ = ATTRIBUTES.set(attributes);
+}
+
+/// The attributes to draw `element` with.
+pub fn attributes(element: Element) -> attr_t {
+    ATTRIBUTES
+        .get()
+        .map_or_else(A_NORMAL, |attributes| attributes[element as usize])
+}
+
+/// Puts the attributes of `over` on top of `base`. The colors of `over` win if it has any,
+/// as color pairs can't be combined.
+pub fn merge(base: attr_t, over: attr_t) -> attr_t {
+    if over & A_COLOR() != 0 {
+        (base & !A_COLOR()) | over
+    } else {
+        base | over
+    }
+}
+
+fn parse_color(name: &str) -> Option<i16> {
+    if name == "default" {
+        return Some(-1);
+    }
+    if let Some(&(_, color)) = COLOR_NAMES.iter().find(|&&(n, _)| n == name) {
+        return Some(color);
+    }
+    if let Some(&(_, color)) = name
+        .strip_prefix("bright-")
+        .and_then(|name| COLOR_NAMES.iter().find(|&&(n, _)| n == name))
+    {
+        return Some(color + 8);
+    }
+    match name.parse::<i16>() {
+        Ok(color) if (0..256).contains(&color) => Some(color),
+        _ => None,
+    }
+}